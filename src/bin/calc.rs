@@ -0,0 +1,306 @@
+use std::env;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+use std::process;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use basic_calculator::calculator::Calculator;
+use basic_calculator::{Environment, Error, EvalError, Spanned};
+
+const USAGE: &str = "usage: calc [OPTIONS] [EXPRESSION...]
+
+Evaluates EXPRESSION and prints the result. Without arguments, expressions
+are read line by line from stdin, or from an interactive prompt when stdin
+is a terminal. Arguments after `--` are never read as options.
+
+options:
+  --mode MODE     number representation: float (default), decimal, rational,
+                  complex or integer
+  --digits N      significant digits kept in decimal mode
+  --fraction FMT  show rational results as fraction (default), mixed or decimal
+  --complex FMT   show complex results as rectangular (default) or polar
+  --angle UNIT    trigonometry in radians (default), degrees or gradians
+  --integer TYPE  word size of integer mode: i8, u8, i16, u16, i32, u32,
+                  i64 (default) or u64
+  --implicit on|off
+                  read 2x as 2*x (default on; off is strict mode)
+  --constants     list the built-in and user constants and exit
+  --units         list the built-in and user units and exit
+  --update-rates  fetch the latest exchange rates before evaluating
+
+Constants and units are read from $CALC_CONSTANTS and $CALC_UNITS, or from
+~/.config/basic-calculator/constants and ~/.config/basic-calculator/units,
+one `name = value  # description` per line. Exchange rates come from
+$CALC_RATES_URL (default https://api.frankfurter.app) and are cached in
+$CALC_RATES_CACHE or ~/.cache/basic-calculator/rates.json.";
+
+const REPL_HELP: &str = ":mode MODE      switch to float, decimal, rational, complex or integer numbers
+:digits N       keep N significant digits in decimal mode
+:fraction FMT   show rational results as fraction, mixed or decimal
+:complex FMT    show complex results as rectangular or polar
+:angle UNIT     use radians, degrees or gradians for trigonometry
+:integer TYPE   integer mode word size, e.g. u8 or i32
+:implicit on|off  allow or reject implicit multiplication such as 2x
+:constants      list the constants
+:units          list the units
+:rates          fetch the latest exchange rates
+exit, quit      leave the calculator";
+
+pub fn main() {
+    let (mut args, expression) = split_arguments(env::args().skip(1).collect());
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let mut env = Calculator::environment();
+    if args.iter().any(|a| a == "--constants") {
+        print_constants(&env);
+        return;
+    }
+    if args.iter().any(|a| a == "--units") {
+        print_units(&env);
+        return;
+    }
+    if args.iter().any(|a| a == "--update-rates") {
+        args.retain(|a| a != "--update-rates");
+        if let Err(message) = update_rates(&mut env) {
+            eprintln!("{}", message);
+        }
+    }
+    let args = match parse_options(args, &mut env) {
+        Ok(rest) => rest.into_iter().chain(expression).collect::<Vec<String>>(),
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    let ok = if !args.is_empty() {
+        eval_and_print(&args.join(" "), &mut env)
+    } else if io::stdin().is_terminal() {
+        repl(&mut env)
+    } else {
+        eval_lines(&mut env)
+    };
+    if !ok {
+        process::exit(1);
+    }
+}
+
+/* the arguments before a bare `--` and those after it, which are expression
+   even when they look like options */
+fn split_arguments(mut args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    match args.iter().position(|a| a == "--") {
+        Some(i) => {
+            let rest = args.split_off(i + 1);
+            args.pop();
+            (args, rest)
+        }
+        None => (args, Vec::new()),
+    }
+}
+
+/* applies leading options to `env` and returns the remaining arguments */
+fn parse_options(args: Vec<String>, env: &mut Environment) -> Result<Vec<String>, String> {
+    let mut args = args.into_iter().peekable();
+    while let Some(option) = args.next_if(|a| a.starts_with("--")) {
+        let value = args.next().ok_or(format!("missing value for {}", option))?;
+        set_option(option.trim_start_matches("--"), &value, env)?;
+    }
+    Ok(args.collect())
+}
+
+fn set_option(name: &str, value: &str, env: &mut Environment) -> Result<(), String> {
+    match name {
+        "mode" => env.settings.mode = value.parse()?,
+        "digits" => {
+            env.settings.precision = value.parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or(format!("invalid number of digits '{}'", value))?;
+        }
+        "fraction" => env.settings.fraction = value.parse()?,
+        "complex" => env.settings.complex = value.parse()?,
+        "angle" => env.settings.angle = value.parse()?,
+        "integer" => env.settings.integer = value.parse()?,
+        "implicit" => {
+            env.settings.implicit_multiplication = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("expected on or off but found '{}'", value)),
+            };
+        }
+        _ => return Err(format!("unknown option '{}'", name)),
+    }
+    Ok(())
+}
+
+/* handles a `:command` typed at the prompt */
+fn command(line: &str, env: &mut Environment) {
+    let mut words = line[1..].split_whitespace();
+    let result = match (words.next(), words.next()) {
+        (Some("help"), None) => {
+            println!("{}", REPL_HELP);
+            Ok(())
+        }
+        (Some("constants"), None) => {
+            print_constants(env);
+            Ok(())
+        }
+        (Some("units"), None) => {
+            print_units(env);
+            Ok(())
+        }
+        (Some("rates"), None) => update_rates(env),
+        (Some(name), Some(value)) => set_option(name, value, env),
+        _ => Err(format!("unknown command '{}', try :help", line)),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+    }
+}
+
+fn print_constants(env: &Environment) {
+    let width = env.constants.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for c in env.constants.iter() {
+        println!("{:width$}  {:<20}  {}", c.name, display_literal(&c.literal), c.description, width = width);
+    }
+}
+
+/* fetches the latest exchange rates, or falls back to the cached ones, and defines the currencies */
+fn update_rates(env: &mut Environment) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let rates = runtime.block_on(Calculator::fetch_rates())?;
+    rates.install(&mut env.units);
+    eprintln!("exchange rates of {} for {} currencies", rates.date, rates.rates.len() + 1);
+    Ok(())
+}
+
+fn print_units(env: &Environment) {
+    let width = env.units.iter().map(|u| u.name.len()).max().unwrap_or(0);
+    for u in env.units.iter() {
+        let size = format!("{} {}", u.factor, u.dim);
+        println!("{:width$}  {:<24}  {}", u.name, size, u.description, width = width);
+    }
+}
+
+/* shortens the long mathematical constants to what fits the listing */
+fn display_literal(literal: &str) -> &str {
+    match literal.find('.') {
+        Some(point) if !literal.contains('e') => &literal[..literal.len().min(point + 16)],
+        _ => literal,
+    }
+}
+
+/* prints the value of `input`, or its error on stderr; returns false on error */
+fn eval_and_print(input: &str, env: &mut Environment) -> bool {
+    let input = input.trim_end();
+    match Calculator::evaluate(input, env) {
+        Ok(value) => {
+            println!("{}", value.format(&env.settings));
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e.render(input));
+            if let Error::Eval(Spanned { node: EvalError::RatesNotLoaded(_), .. }) = e {
+                eprintln!("load exchange rates with --update-rates, or :rates at the prompt");
+            }
+            false
+        }
+    }
+}
+
+fn eval_lines(env: &mut Environment) -> bool {
+    let mut ok = true;
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return false;
+            }
+        };
+        if !line.trim().is_empty() {
+            ok &= eval_and_print(&line, env);
+        }
+    }
+    ok
+}
+
+fn repl(env: &mut Environment) -> bool {
+    let mut editor = match Editor::<()>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return false;
+        }
+    };
+    let history = history_path();
+    if let Some(path) = &history {
+        // a missing history file just means this is the first session
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(line);
+                if line == "exit" || line == "quit" {
+                    break;
+                }
+                if line.starts_with(':') {
+                    command(line, env);
+                    continue;
+                }
+                eval_and_print(line, env);
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("could not save history: {}", e);
+        }
+    }
+    true
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".calc_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_end_at_a_bare_separator() {
+        assert_eq!(split_arguments(args("--mode decimal -- --1")), (args("--mode decimal"), args("--1")));
+        assert_eq!(split_arguments(args("-- 1")), (args(""), args("1")));
+        assert_eq!(split_arguments(args("1 + 2")), (args("1 + 2"), args("")));
+
+        let mut env = Environment::new();
+        let (options, _) = split_arguments(args("--digits 5 --"));
+        assert_eq!(parse_options(options, &mut env), Ok(Vec::new()));
+        assert_eq!(env.settings.precision, 5);
+    }
+}
@@ -0,0 +1,131 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::currency::{FileRates, HttpRates, Rates, RatesProvider, DEFAULT_RATES_URL};
+use crate::parser::env::Environment;
+use crate::parser::error::Error;
+use crate::parser::value::Value;
+
+pub struct Calculator {
+    pub input_string: String,
+    pub output_string: String,
+}
+
+impl Calculator {
+
+    /* evaluates `input_string` in `env`, which the caller has set up with
+       its settings, exchange rates and previous answer */
+    pub async fn calculate(
+        input_string: String,
+        mut env: Environment,
+    ) -> Result<Value, String> {
+        let input = input_string.trim_end();
+        Self::evaluate(input, &mut env).map_err(|e| e.render(input))
+    }
+
+    /* a fresh environment with the built-in constants and units, those from
+       the user's files and the cached exchange rates; a broken file is
+       reported and otherwise ignored */
+    pub fn environment() -> Environment {
+        let mut env = Environment::new();
+        if let Err(message) = Self::load_constants(&mut env) {
+            eprintln!("{}", message);
+        }
+        if let Err(message) = Self::load_units(&mut env) {
+            eprintln!("{}", message);
+        }
+        if let Some(rates) = Self::rates_cache().and_then(|cache| cache.read().ok()) {
+            rates.install(&mut env.units);
+        }
+        env
+    }
+
+    /* fetches the latest exchange rates from $CALC_RATES_URL, or the default
+       service, and caches them; offline, the cached rates are used instead */
+    pub async fn fetch_rates() -> Result<Rates, String> {
+        let url = env::var("CALC_RATES_URL").unwrap_or_else(|_| DEFAULT_RATES_URL.to_string());
+        let cache = Self::rates_cache();
+        match HttpRates::new(&url).rates().await {
+            Ok(rates) => {
+                if let Some(cache) = &cache {
+                    if let Err(message) = cache.save(&rates) {
+                        eprintln!("could not cache exchange rates: {}", message);
+                    }
+                }
+                Ok(rates)
+            }
+            Err(message) => match cache {
+                Some(cache) => cache.rates().await.map_err(|_| message),
+                None => Err(message),
+            },
+        }
+    }
+
+    /* $CALC_HISTORY, or history.json in the calculator's data directory */
+    pub fn history_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("CALC_HISTORY") {
+            return Some(PathBuf::from(path));
+        }
+        let data = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+        Some(data.join("basic-calculator").join("history.json"))
+    }
+
+    /* $CALC_RATES_CACHE, or rates.json in the calculator's cache directory */
+    pub fn rates_cache() -> Option<FileRates> {
+        if let Some(path) = env::var_os("CALC_RATES_CACHE") {
+            return Some(FileRates::new(PathBuf::from(path)));
+        }
+        let cache = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(FileRates::new(cache.join("basic-calculator").join("rates.json")))
+    }
+
+    /* adds the constants in `config_path("constants")` to `env`, if that file exists */
+    pub fn load_constants(env: &mut Environment) -> Result<(), String> {
+        Self::load_config("constants", |text| env.constants.load(text))
+    }
+
+    /* adds the units in `config_path("units")` to `env`, if that file exists */
+    pub fn load_units(env: &mut Environment) -> Result<(), String> {
+        Self::load_config("units", |text| env.load_units(text))
+    }
+
+    /* runs `load` on the config file `name`, if it exists */
+    pub fn load_config(name: &str, load: impl FnOnce(&str) -> Result<(), String>) -> Result<(), String> {
+        let path = match Self::config_path(name) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        load(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /* $CALC_CONSTANTS for "constants" and so on, or the file of that name in
+       the calculator's config directory */
+    pub fn config_path(name: &str) -> Option<PathBuf> {
+        if let Some(path) = env::var_os(format!("CALC_{}", name.to_uppercase())) {
+            return Some(PathBuf::from(path));
+        }
+        let config = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config.join("basic-calculator").join(name))
+    }
+
+    /* evaluates `input` and keeps the result as `ans` for the next calculation */
+    pub fn evaluate(input: &str, env: &mut Environment) -> Result<Value, Error> {
+        let value = crate::eval(input, env)?;
+        env.set("ans", value.clone());
+        Ok(value)
+    }
+
+}
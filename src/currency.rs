@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::parser::units::{Units, CURRENCY};
+
+/* serves the latest European Central Bank rates without an API key */
+pub const DEFAULT_RATES_URL: &str = "https://api.frankfurter.app";
+
+/* exchange rates in the format of the rates service, which is also how they are cached */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rates {
+    /* the currency the rates are quoted against */
+    pub base: String,
+    /* the day the rates are from, as given by the provider */
+    #[serde(default)]
+    pub date: String,
+    /* units of each currency per one unit of `base` */
+    pub rates: BTreeMap<String, f64>,
+}
+
+impl Rates {
+    /* defines every currency as a unit, so `100 USD to EUR` converts */
+    pub fn install(&self, units: &mut Units) {
+        let description = format!("currency, rates of {}", self.date);
+        units.define(&self.base, 1.0, CURRENCY, &description);
+        for (code, rate) in &self.rates {
+            if rate.is_finite() && *rate > 0.0 {
+                units.define(code, 1.0 / rate, CURRENCY, &description);
+            }
+        }
+    }
+}
+
+/* a source of exchange rates */
+pub trait RatesProvider {
+    fn rates(&self) -> impl Future<Output = Result<Rates, String>> + Send;
+}
+
+/* fetches `<base_url>/latest` from a Frankfurter compatible service */
+#[derive(Debug, Clone)]
+pub struct HttpRates {
+    pub base_url: String,
+    client: reqwest::Client,
+}
+
+impl HttpRates {
+    pub fn new(base_url: &str) -> HttpRates {
+        HttpRates {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl RatesProvider for HttpRates {
+    async fn rates(&self) -> Result<Rates, String> {
+        let url = format!("{}/latest", self.base_url);
+        let response = self.client.get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("could not fetch exchange rates: {}", e))?;
+        response.json::<Rates>()
+            .await
+            .map_err(|e| format!("invalid exchange rates from {}: {}", url, e))
+    }
+}
+
+/* rates saved by an earlier fetch, for working offline */
+#[derive(Debug, Clone)]
+pub struct FileRates {
+    pub path: PathBuf,
+}
+
+impl FileRates {
+    pub fn new(path: PathBuf) -> FileRates {
+        FileRates { path }
+    }
+
+    pub fn read(&self) -> Result<Rates, String> {
+        let text = fs::read_to_string(&self.path)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+
+    pub fn save(&self, rates: &Rates) -> Result<(), String> {
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&self.path, serde_json::to_string_pretty(rates)?)
+        };
+        write().map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+}
+
+impl RatesProvider for FileRates {
+    async fn rates(&self) -> Result<Rates, String> {
+        self.read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use crate::parser::env::Environment;
+    use crate::parser::error::{Error, EvalError};
+
+    /* fixed rates quoted against the euro */
+    struct FixedRates;
+
+    impl RatesProvider for FixedRates {
+        async fn rates(&self) -> Result<Rates, String> {
+            Ok(sample())
+        }
+    }
+
+    fn sample() -> Rates {
+        Rates {
+            base: "EUR".to_string(),
+            date: "2024-01-02".to_string(),
+            rates: BTreeMap::from([
+                ("USD".to_string(), 1.25),
+                ("GBP".to_string(), 0.8),
+                ("XXX".to_string(), 0.0),
+            ]),
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+    }
+
+    /* answers one HTTP request on a local port with `body`; the handle gives
+       back the request line */
+    fn serve_once(body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            let request = lines.next().unwrap().unwrap();
+            while !lines.next().unwrap().unwrap().is_empty() {}
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body,
+            ).unwrap();
+            request
+        });
+        (url, server)
+    }
+
+    fn convert(rates: &Rates, input: &str) -> Result<String, Error> {
+        let mut env = Environment::new();
+        rates.install(&mut env.units);
+        crate::eval(input, &mut env).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn install_defines_every_usable_currency() {
+        let mut units = Units::default();
+        sample().install(&mut units);
+        for code in ["EUR", "USD", "GBP"] {
+            let def = units.get(code).unwrap();
+            assert_eq!(def.dim, CURRENCY, "{}", code);
+            assert!(def.description.contains("2024-01-02"));
+        }
+        assert_eq!(units.get("USD").unwrap().factor, 0.8);
+        // a rate of zero cannot be inverted
+        assert!(units.get("XXX").is_none());
+    }
+
+    #[test]
+    fn converts_with_rates_from_a_provider() {
+        let rates = block_on(FixedRates.rates()).unwrap();
+        assert_eq!(convert(&rates, "100 USD to EUR").unwrap(), "80 EUR");
+        assert_eq!(convert(&rates, "10 EUR + 10 GBP in EUR").unwrap(), "22.5 EUR");
+        assert!(convert(&rates, "1 USD to m").is_err());
+    }
+
+    #[test]
+    fn fetches_rates_over_http() {
+        let (url, server) = serve_once(
+            r#"{"amount":1.0,"base":"EUR","date":"2024-01-02","rates":{"GBP":0.8,"USD":1.25}}"#,
+        );
+        let rates = block_on(HttpRates::new(&format!("{}/", url)).rates()).unwrap();
+        assert_eq!(server.join().unwrap(), "GET /latest HTTP/1.1");
+        assert_eq!(rates.date, "2024-01-02");
+        assert_eq!(convert(&rates, "100 USD to EUR").unwrap(), "80 EUR");
+        assert_eq!(convert(&rates, "250 USD to GBP").unwrap(), "160 GBP");
+    }
+
+    #[test]
+    fn cached_rates_round_trip_through_a_file() {
+        let path = std::env::temp_dir().join(format!("calc-rates-{}.json", std::process::id()));
+        let cache = FileRates::new(path.clone());
+        cache.save(&sample()).unwrap();
+        let rates = block_on(cache.rates());
+        fs::remove_file(&path).unwrap();
+        let rates = rates.unwrap();
+        assert_eq!(rates, sample());
+        assert_eq!(convert(&rates, "250 USD to GBP").unwrap(), "160 GBP");
+        assert!(FileRates::new(path).read().is_err());
+    }
+
+    #[test]
+    fn currencies_without_rates_say_so() {
+        match crate::eval("100 USD to EUR", &mut Environment::new()) {
+            Err(Error::Eval(e)) => assert_eq!(e.node, EvalError::RatesNotLoaded("USD".to_string())),
+            other => panic!("expected a missing rates error, got {:?}", other),
+        }
+    }
+}
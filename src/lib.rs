@@ -0,0 +1,45 @@
+//! Expression parser and evaluator behind the calculator.
+//!
+//! ```
+//! use basic_calculator::{eval, Environment, Value};
+//!
+//! let mut env = Environment::new();
+//! eval("let f(x) = x^2 + 1", &mut env).unwrap();
+//! assert_eq!(eval("f(3)", &mut env).unwrap(), Value::Float(10.0));
+//! ```
+//!
+//! Build with `default-features = false` to use the library without the
+//! iced GUI and the command-line binary.
+
+pub mod calculator;
+pub mod currency;
+pub mod history;
+pub mod parser;
+
+pub use parser::ast::{Expr, Program};
+pub use parser::constants::{Constant, Constants};
+pub use parser::env::Environment;
+pub use parser::error::{Error, EvalError, ParseError};
+pub use parser::functions::{Arity, NativeFn, Registry};
+pub use parser::settings::{NumberMode, Settings};
+pub use parser::span::{Span, Spanned};
+pub use parser::value::Value;
+
+/* parses `;` or newline separated statements; juxtaposition such as `2x` is multiplication */
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    parser::Parser::new(input).parse_program()
+}
+
+/* like `parse`, but every product must be written with `*` */
+pub fn parse_strict(input: &str) -> Result<Program, ParseError> {
+    parser::Parser::new(input).strict().parse_program()
+}
+
+/* parses and evaluates `input` against `env`, returning the last statement's value */
+pub fn eval(input: &str, env: &mut Environment) -> Result<Value, Error> {
+    let mut parser = parser::Parser::new(input).with_units(&env.units);
+    if !env.settings.implicit_multiplication {
+        parser = parser.strict();
+    }
+    Ok(parser.parse_program()?.eval(env)?)
+}
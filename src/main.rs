@@ -1,26 +1,33 @@
-use std::collections::HashMap;
+use std::ops::Range;
 
 use iced::window::{Position, Icon};
-use iced::{executor, alignment};
-use iced::widget::{button, column, container, text, row};
+use iced::{executor, alignment, subscription};
+use iced::widget::{button, column, container, scrollable, text, row};
 use iced::{
-    Alignment, Application, Command, Length, Settings, window
+    Alignment, Application, Command, Length, Settings, Subscription, window
 }; 
+use self::keys::{Key, KeyBindings};
 use self::theme::Theme;
 use self::widget::Element;
+use basic_calculator::calculator::Calculator;
+use basic_calculator::currency::Rates;
+use basic_calculator::history::History;
+use basic_calculator::{Environment, NumberMode, Settings as CalcSettings, Value};
 
 
-mod parser;
+/* the programmer panel of integer mode needs a taller window */
+const WINDOW_SIZE: (u32, u32) = (300, 500);
+const PROGRAMMER_WINDOW_SIZE: (u32, u32) = (300, 780);
 
 pub fn main() -> iced::Result {
     let settings = Settings {
         window: window::Settings {
-            size: (300, 500),
+            size: WINDOW_SIZE,
             resizable: true,
             decorations: true,
             position: Position::Default,
-            min_size: Some((300, 500)),
-            max_size: Some((300, 500)),
+            min_size: Some(WINDOW_SIZE),
+            max_size: Some(PROGRAMMER_WINDOW_SIZE),
             visible: true,
             transparent: false,
             always_on_top: true,
@@ -31,16 +38,35 @@ pub fn main() -> iced::Result {
     CalculatorGUI::run(settings)
 }
 
-#[derive(Debug)]
 struct CalculatorGUI {
     display_text: String,
+    /* the selection in the expression field, in graphemes; both ends are the
+       cursor when nothing is selected */
+    cursor: (usize, usize),
+    /* why the expression in the display could not be calculated */
+    error: Option<String>,
     done_calculation: bool,
+    settings: CalcSettings,
+    /* last integer result, shown and edited by the programmer panel */
+    int_value: i128,
+    /* name and description of every constant, for the constants panel */
+    constants: Vec<(String, String)>,
+    show_constants: bool,
+    /* past calculations, saved to `Calculator::history_path()` after each one */
+    history: History,
+    show_history: bool,
+    keys: KeyBindings,
+    /* the constants, units and functions loaded at startup, the exchange rates
+       once fetched and the previous result as `ans`; each calculation gets a
+       copy with the current settings */
+    env: Environment,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     StartCalculating,
-    DoneCalculating(String),
+    /* the expression and its value */
+    DoneCalculating(String, Result<Value, String>),
     CE,
     Del,
     Caret,
@@ -68,7 +94,27 @@ pub enum Message {
     Zero,
     Negate,
     Decimal,
-    Typing,
+    ToggleNumberMode,
+    ToggleFractionStyle,
+    ToggleComplexStyle,
+    ToggleAngleUnit,
+    ImaginaryUnit,
+    ToggleIntegerType,
+    ToggleBit(u32),
+    Insert(String),
+    ToggleConstants,
+    Constant(String),
+    RatesLoaded(Result<Rates, String>),
+    ToggleHistory,
+    RecallExpression(usize),
+    RecallResult(usize),
+    ClearHistory,
+    /* a key typed anywhere in the window */
+    Typing(Key),
+    /* the expression as edited in its field */
+    Edit(String),
+    /* the selection in the expression field moved */
+    Cursor(usize, usize),
 }
 
 impl Application for CalculatorGUI {
@@ -78,13 +124,39 @@ impl Application for CalculatorGUI {
     type Flags = ();
 
     fn new(_flags: ()) -> (CalculatorGUI, Command<Message>) {
-        (
-            CalculatorGUI {
-                display_text: "".to_string(),
-                done_calculation: true,
-            },
-            Command::perform(Calculator::calculate("".to_string()), Message::DoneCalculating),
-        )
+        let history = match Calculator::history_path() {
+            Some(path) => History::load(&path).unwrap_or_else(|message| {
+                eprintln!("{}", message);
+                History::default()
+            }),
+            None => History::default(),
+        };
+        let mut keys = KeyBindings::default();
+        if let Err(message) = Calculator::load_config("keys", |text| keys.load(text)) {
+            eprintln!("{}", message);
+        }
+        let env = Calculator::environment();
+        let gui = CalculatorGUI {
+            display_text: "".to_string(),
+            cursor: (0, 0),
+            error: None,
+            done_calculation: true,
+            settings: CalcSettings::default(),
+            int_value: 0,
+            constants: env.constants.iter()
+                .map(|c| (c.name.clone(), c.description.clone()))
+                .collect(),
+            show_constants: false,
+            history,
+            show_history: false,
+            keys,
+            env,
+        };
+        let startup = Command::batch([
+            gui.calculate("".to_string()),
+            Command::perform(Calculator::fetch_rates(), Message::RatesLoaded),
+        ]);
+        (gui, startup)
     }
 
     fn title(&self) -> String {
@@ -93,224 +165,220 @@ impl Application for CalculatorGUI {
 
     fn update(&mut self, message: Message) -> Command<Message> {
         match message {
-            Message::One => {
-                if self.done_calculation {
-                    self.display_text = "1".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "1";
-                }
-                
-                Command::none()
-            },
-            Message::Two => {
-                if self.done_calculation {
-                    self.display_text = "2".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "2";
-                }
-                Command::none()
+            Message::One => self.type_text("1", true),
+            Message::Two => self.type_text("2", true),
+            Message::Three => self.type_text("3", true),
+            Message::Four => self.type_text("4", true),
+            Message::Five => self.type_text("5", true),
+            Message::Six => self.type_text("6", true),
+            Message::Seven => self.type_text("7", true),
+            Message::Eight => self.type_text("8", true),
+            Message::Nine => self.type_text("9", true),
+            Message::Zero => self.type_text("0", true),
+            Message::ImaginaryUnit => self.type_text("i", true),
+            Message::Decimal => self.type_text(".", true),
+            Message::Add => self.type_text(" + ", false),
+            Message::Subtract => self.type_text(" - ", false),
+            Message::Multiply => self.type_text(" * ", false),
+            Message::Divide => self.type_text(" / ", false),
+            Message::Mod => self.type_text(" % ", false),
+            Message::Caret => self.type_text(" ^ ", false),
+            Message::LeftParen => self.type_text("(", true),
+            Message::RightParen => self.type_text(")", true),
+            Message::CE => {
+                self.display_text = "".to_string();
+                self.error = None;
+                self.cursor_to_end()
             },
-            Message::Three => {
-                if self.done_calculation {
-                    self.display_text = "3".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "3";
+            Message::Del => {
+                // the selection, or the character before the cursor
+                let mut range = self.selection();
+                if range.is_empty() {
+                    range.start = expression::byte_offset(&self.display_text, self.cursor.0.saturating_sub(1));
                 }
-                Command::none()
+                self.display_text.replace_range(range.clone(), "");
+                let at = expression::graphemes(&self.display_text[..range.start]);
+                self.cursor = (at, at);
+                expression::focus(at)
             },
-            Message::Four => {
-                if self.done_calculation {
-                    self.display_text = "4".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "4";
-                }
-                Command::none()
+            Message::Sin => {
+                let range = self.operand();
+                self.wrap(range, "sin(", ")")
             },
-            Message::Five => {
-                if self.done_calculation {
-                    self.display_text = "5".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "5";
-                }
-                Command::none()
+            Message::Cos => {
+                let range = self.operand();
+                self.wrap(range, "cos(", ")")
             },
-            Message::Six => {
-                if self.done_calculation {
-                    self.display_text = "6".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "6";
-                }
-                Command::none()
+            Message::Tan => {
+                let range = self.operand();
+                self.wrap(range, "tan(", ")")
             },
-            Message::Seven => {
-                if self.done_calculation {
-                    self.display_text = "7".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "7";
-                }
-                Command::none()
+            Message::Negate => {
+                // toggles a prefix minus around the whole entry
+                self.display_text = match negated(&self.display_text) {
+                    Some(inner) => inner.to_string(),
+                    None if self.display_text.is_empty() => "-".to_string(),
+                    None => format!("-({})", self.display_text),
+                };
+                self.done_calculation = false;
+                self.cursor_to_end()
             },
-            Message::Eight => {
-                if self.done_calculation {
-                    self.display_text = "8".to_string();
-                    self.done_calculation = false;
+            Message::Factorial => {
+                // postfix, so a single operand needs no parentheses
+                let range = self.operand();
+                let operand = &self.display_text[range.clone()];
+                if operand_at(operand, operand.len()) == (0..operand.len()) {
+                    self.wrap(range, "", "!")
                 } else {
-                    self.display_text += "8";
+                    self.wrap(range, "(", ")!")
                 }
-                Command::none()
             },
-            Message::Nine => {
-                if self.done_calculation {
-                    self.display_text = "9".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "9";
-                }
-                Command::none()
+            Message::Equals => {
+                let dup_str = std::mem::take(&mut self.display_text);
+                self.cursor = (0, 0);
+                self.calculate(dup_str)
             },
-            Message::Zero => { 
-                if self.done_calculation {
-                    self.display_text = "0".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "0";
-                }
-                Command::none()
+            Message::StartCalculating => {
+                self.calculate("4 + 4".to_string())
             },
-            Message::Decimal => {
-                if self.done_calculation {
-                    self.display_text = ".".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += ".";
+            Message::ToggleNumberMode => {
+                let was_integer = self.settings.mode == NumberMode::Integer;
+                self.settings.mode = self.settings.mode.next();
+                match (was_integer, self.settings.mode == NumberMode::Integer) {
+                    (false, true) => window::resize(PROGRAMMER_WINDOW_SIZE.0, PROGRAMMER_WINDOW_SIZE.1),
+                    (true, false) => window::resize(WINDOW_SIZE.0, WINDOW_SIZE.1),
+                    _ => Command::none(),
                 }
-                Command::none()
             },
-            Message::Add => {
-                self.display_text += " + ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
+            Message::ToggleIntegerType => {
+                self.settings.integer = self.settings.integer.next();
+                self.int_value = self.settings.integer.wrap(self.int_value);
                 Command::none()
             },
-            Message::Subtract => {
-                self.display_text += " - ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
-                Command::none()
+            Message::ToggleBit(bit) => {
+                self.int_value = self.settings.integer.wrap(self.int_value ^ (1i128 << bit));
+                self.display_text = self.int_value.to_string();
+                self.done_calculation = true;
+                self.cursor_to_end()
             },
-            Message::Multiply => {
-                self.display_text += " * ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
+            Message::Insert(s) => self.type_text(&s, false),
+            Message::ToggleConstants => {
+                self.show_constants = !self.show_constants;
+                self.show_history = false;
                 Command::none()
             },
-            Message::Divide => {
-                self.display_text += " / ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
-                Command::none()
+            Message::Constant(name) => {
+                self.show_constants = false;
+                self.type_text(&name, true)
             },
-            Message::Mod => {
-                self.display_text += " % ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
+            Message::ToggleFractionStyle => {
+                self.settings.fraction = self.settings.fraction.next();
                 Command::none()
             },
-            Message::CE => {
-                self.display_text = "".to_string();
+            Message::ToggleComplexStyle => {
+                self.settings.complex = self.settings.complex.next();
                 Command::none()
             },
-            Message::Del => {
-                let mut temp_display_text = self.display_text.to_string();
-                temp_display_text.pop();
-                self.display_text = temp_display_text;
+            Message::ToggleAngleUnit => {
+                self.settings.angle = self.settings.angle.next();
                 Command::none()
             },
-            Message::Caret => {
-                self.display_text += " ^ ";
-                if self.done_calculation {
-                    self.done_calculation = false;
-                }
+            Message::RatesLoaded(Ok(rates)) => {
+                rates.install(&mut self.env.units);
                 Command::none()
             },
-            Message::Sin => {
-                self.display_text = format!("sin({})", self.display_text);
+            Message::RatesLoaded(Err(message)) => {
+                eprintln!("{}", message);
                 Command::none()
             },
-            Message::Cos => {
-                self.display_text = format!("cos({})", self.display_text);
-                Command::none()
+            Message::DoneCalculating(expression, result) => {
+                match result {
+                    Ok(value) => {
+                        let result = value.format(&self.settings);
+                        // the empty startup calculation is not worth remembering
+                        if !expression.trim().is_empty() {
+                            self.history.push(expression.trim(), &result);
+                            self.save_history();
+                            self.env.set("ans", value);
+                        }
+                        if let Ok(n) = result.parse() {
+                            self.int_value = n;
+                        }
+                        self.display_text = result;
+                        self.error = None;
+                        self.done_calculation = true;
+                    },
+                    // the expression stays in the display to be corrected
+                    Err(message) => {
+                        self.display_text = expression;
+                        self.error = Some(message);
+                        self.done_calculation = false;
+                    },
+                }
+                self.cursor_to_end()
             },
-            Message::Tan => {
-                self.display_text = format!("tan({})", self.display_text);
+            Message::ToggleHistory => {
+                self.show_history = !self.show_history;
+                self.show_constants = false;
                 Command::none()
             },
-            Message::LeftParen => {
-                if self.done_calculation {
-                    self.display_text = "(".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += "(";
+            Message::RecallExpression(index) => {
+                self.show_history = false;
+                match self.history.entries.get(index) {
+                    Some(entry) => {
+                        self.display_text = entry.expression.clone();
+                        self.done_calculation = false;
+                        self.cursor_to_end()
+                    },
+                    None => Command::none(),
                 }
-                Command::none()
             },
-            Message::RightParen => {
-                if self.done_calculation {
-                    self.display_text = ")".to_string();
-                    self.done_calculation = false;
-                } else {
-                    self.display_text += ")";
+            Message::RecallResult(index) => {
+                self.show_history = false;
+                match self.history.entries.get(index) {
+                    Some(entry) => {
+                        let result = entry.result.clone();
+                        self.type_text(&result, true)
+                    },
+                    None => Command::none(),
                 }
-                Command::none()
             },
-            Message::Negate => {
-                self.display_text = format!("(-{})", self.display_text);
-                Command::none()
+            Message::Typing(key) => match (self.keys.get(key), key) {
+                (Some(message), _) => self.update(message),
+                // an unbound character types itself; like a digit, a name
+                // starts a new expression after a result
+                (None, Key::Char(c)) => self.type_text(&c.to_string(), c.is_alphanumeric() || c == '_'),
+                (None, Key::Named(_)) => Command::none(),
             },
-            Message::Factorial => {
-                self.display_text = format!("fact({})", self.display_text);
+            Message::Edit(text) => {
+                self.display_text = text;
+                self.error = None;
+                self.done_calculation = false;
                 Command::none()
             },
-            Message::Equals => {
-                let dup_str = self.display_text.clone();
-                self.display_text = "".to_string();
-                Command::perform(Calculator::calculate(dup_str), Message::DoneCalculating)
-            },
-            Message::StartCalculating => {
-                Command::perform(Calculator::calculate("4 + 4".to_string()), Message::DoneCalculating)
-            },
-            Message::DoneCalculating(result) => {
-                self.display_text = result;
-                self.done_calculation = true;
+            Message::Cursor(start, end) => {
+                self.cursor = (start, end);
                 Command::none()
             },
-            _ => {
+            Message::ClearHistory => {
+                self.history.clear();
+                self.save_history();
                 Command::none()
-            }
+            },
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn subscription(&self) -> Subscription<Message> {
+        subscription::events_with(keys::key_event)
+    }
+
+    fn view(&self) -> Element<'_, Message> {
         let title = text("Calculator")
         .width(Length::Fill)
         .size(20)
         .horizontal_alignment(alignment::Horizontal::Center);
         
-        let display_text = button(text(format!("{}", self.display_text)))
-            .style(theme::Button::Primary)
-            .width(Length::Fill);
+        let display_text = expression::input(&self.display_text, self.cursor, &self.keys);
 
         let ce_btn = button("CE")
         .style(theme::Button::Secondary)
@@ -393,7 +461,7 @@ impl Application for CalculatorGUI {
 
         let four_btn = button("4")
             .style(theme::Button::Secondary)
-            .on_press(Message::Four);;
+            .on_press(Message::Four);
 
         let five_btn = button("5")
             .style(theme::Button::Secondary)
@@ -427,8 +495,45 @@ impl Application for CalculatorGUI {
             .style(theme::Button::Secondary)
             .on_press(Message::Decimal);
             
-        let first_row = row![title].spacing(20).padding(10).align_items(Alignment::Start);
-        let second_row = row![display_text].padding(32).align_items(Alignment::Start);
+        let mode_btn = button(self.settings.mode.label())
+            .style(theme::Button::Secondary)
+            .on_press(Message::ToggleNumberMode);
+
+        // only rational mode has fractions, so the style button follows the mode
+        let style_btn = match self.settings.mode {
+            NumberMode::Complex => button(self.settings.complex.label())
+                .style(theme::Button::Secondary)
+                .on_press(Message::ToggleComplexStyle),
+            NumberMode::Integer => button(self.settings.integer.label())
+                .style(theme::Button::Secondary)
+                .on_press(Message::ToggleIntegerType),
+            _ => button(self.settings.fraction.label())
+                .style(theme::Button::Secondary)
+                .on_press(Message::ToggleFractionStyle),
+        };
+
+        let angle_btn = button(self.settings.angle.label())
+            .style(theme::Button::Secondary)
+            .on_press(Message::ToggleAngleUnit);
+
+        let constants_btn = button("const")
+            .style(theme::Button::Secondary)
+            .on_press(Message::ToggleConstants);
+
+        let history_btn = button("hist")
+            .style(theme::Button::Secondary)
+            .on_press(Message::ToggleHistory);
+
+        let mut first_row = row![title, mode_btn, style_btn, angle_btn, constants_btn, history_btn].spacing(10).padding(10).align_items(Alignment::Start);
+        if self.settings.mode == NumberMode::Complex {
+            first_row = first_row.push(button("i")
+                .style(theme::Button::Secondary)
+                .on_press(Message::ImaginaryUnit));
+        }
+        let mut second_row = column![display_text].spacing(5).padding(32).align_items(Alignment::Start);
+        if let Some(error) = &self.error {
+            second_row = second_row.push(text(error).size(14));
+        }
         let fourth_row = row![sin_btn, cos_btn, tan_btn, del_btn, ce_btn].spacing(5);
         let fifth_row = row![factorial_btn, mod_btn, negate_btn, caret_btn, left_paren_btn, right_paren_btn].spacing(13);
         let sixth_row = row![seven_btn, eight_btn, nine_btn, divide_btn].spacing(20);
@@ -436,9 +541,19 @@ impl Application for CalculatorGUI {
         let eighth_row = row![one_btn, two_btn, three_btn, subtract_btn].spacing(20);
         let ninth_row = row![equals_btn, zero_btn, decimal_btn, add_btn].spacing(20);
 
-        let content = column![first_row, second_row, fourth_row, fifth_row, sixth_row, seventh_row, eighth_row, ninth_row]
+        // the constants and history lists take the place of the keypad while open
+        let mut content = if self.show_constants {
+            column![first_row, second_row, self.constants_panel()]
+        } else if self.show_history {
+            column![first_row, second_row, self.history_panel()]
+        } else {
+            column![first_row, second_row, fourth_row, fifth_row, sixth_row, seventh_row, eighth_row, ninth_row]
+        }
             .align_items(Alignment::Center)
             .spacing(20);
+        if self.settings.mode == NumberMode::Integer {
+            content = content.push(self.programmer_panel());
+        }
 
         container(content)
             .width(Length::Fill)
@@ -452,57 +567,663 @@ impl Application for CalculatorGUI {
 
 }
 
-pub struct Calculator {
-    pub input_string: String,
-    pub output_string: String,
+impl CalculatorGUI {
+    /* evaluates `expression` with the current settings, exchange rates and `ans` */
+    fn calculate(&self, expression: String) -> Command<Message> {
+        let mut env = self.env.clone();
+        env.settings = self.settings;
+        Command::perform(Calculator::calculate(expression.clone(), env), move |result| {
+            Message::DoneCalculating(expression, result)
+        })
+    }
+
+    /* the selected part of the expression as a byte range, empty at the cursor */
+    fn selection(&self) -> Range<usize> {
+        expression::byte_offset(&self.display_text, self.cursor.0)..expression::byte_offset(&self.display_text, self.cursor.1)
+    }
+
+    /* what the function buttons apply to: the selection, the operand under the
+       cursor, or after a result the whole result */
+    fn operand(&self) -> Range<usize> {
+        let selection = self.selection();
+        if self.done_calculation {
+            0..self.display_text.len()
+        } else if selection.is_empty() {
+            operand_at(&self.display_text, selection.start)
+        } else {
+            selection
+        }
+    }
+
+    /* types `text` over the selection or at the cursor; after a result a new
+       `operand`, such as a digit, replaces it and anything else continues it */
+    fn type_text(&mut self, text: &str, operand: bool) -> Command<Message> {
+        if self.done_calculation {
+            if operand {
+                self.display_text.clear();
+            }
+            let end = expression::graphemes(&self.display_text);
+            self.cursor = (end, end);
+            self.done_calculation = false;
+        }
+        let range = self.selection();
+        self.display_text.replace_range(range.clone(), text);
+        self.error = None;
+        let at = expression::graphemes(&self.display_text[..range.start + text.len()]);
+        self.cursor = (at, at);
+        expression::focus(at)
+    }
+
+    /* puts `prefix` and `suffix` around `range`; the cursor goes between them
+       when the range is empty, so `sin(` can be followed by its argument */
+    fn wrap(&mut self, range: Range<usize>, prefix: &str, suffix: &str) -> Command<Message> {
+        let wrapped = format!("{}{}{}", prefix, &self.display_text[range.clone()], suffix);
+        let end = if range.is_empty() && !prefix.is_empty() {
+            range.start + prefix.len()
+        } else {
+            range.start + wrapped.len()
+        };
+        self.display_text.replace_range(range, &wrapped);
+        self.error = None;
+        self.done_calculation = false;
+        let at = expression::graphemes(&self.display_text[..end]);
+        self.cursor = (at, at);
+        expression::focus(at)
+    }
+
+    fn cursor_to_end(&mut self) -> Command<Message> {
+        let end = expression::graphemes(&self.display_text);
+        self.cursor = (end, end);
+        expression::focus(end)
+    }
+
+    fn save_history(&self) {
+        if let Some(path) = Calculator::history_path() {
+            if let Err(message) = self.history.save(&path) {
+                eprintln!("could not save history: {}", message);
+            }
+        }
+    }
+
+    /* the last integer result in hex, decimal and octal, its bits as toggles,
+       and the bitwise operators */
+    fn programmer_panel(&self) -> Element<'_, Message> {
+        let ty = self.settings.integer;
+        // two's complement bit pattern within the word size
+        let bits = (self.int_value as u128) & ty.mask();
+        let bases = column![
+            text(format!("HEX  {:X}", bits)).size(14),
+            text(format!("DEC  {}", self.int_value)).size(14),
+            text(format!("OCT  {:o}", bits)).size(14),
+        ].spacing(2);
+
+        let mut bit_rows = column![].spacing(2);
+        for high in (0..ty.bits).rev().step_by(16) {
+            let mut bit_row = row![text(format!("{:>2}", high)).size(12)].spacing(2);
+            for bit in (high.saturating_sub(15)..=high).rev() {
+                let label = if (bits >> bit) & 1 == 1 { "1" } else { "0" };
+                bit_row = bit_row.push(button(text(label).size(12))
+                    .padding(2)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::ToggleBit(bit)));
+            }
+            bit_rows = bit_rows.push(bit_row);
+        }
+
+        let mut operators = row![].spacing(5);
+        for (label, insert) in [("&", " & "), ("|", " | "), ("xor", " xor "), ("~", "~"), ("<<", " << "), (">>", " >> ")] {
+            operators = operators.push(button(label)
+                .style(theme::Button::Secondary)
+                .on_press(Message::Insert(insert.to_string())));
+        }
+
+        column![bases, bit_rows, operators]
+            .spacing(10)
+            .align_items(Alignment::Start)
+            .into()
+    }
+
+    /* one button per constant, inserting its name into the display */
+    fn constants_panel(&self) -> Element<'_, Message> {
+        let mut list = column![].spacing(2);
+        for (name, description) in &self.constants {
+            list = list.push(button(column![text(name).size(14), text(description).size(11)])
+                .width(Length::Fill)
+                .style(theme::Button::Secondary)
+                .on_press(Message::Constant(name.clone())));
+        }
+        scrollable(list).height(Length::Fill).into()
+    }
+
+    /* past calculations, newest first; the expression recalls the whole
+       expression, the result inserts the result into the display */
+    fn history_panel(&self) -> Element<'_, Message> {
+        let mut list = column![button("clear")
+            .style(theme::Button::Secondary)
+            .on_press(Message::ClearHistory)].spacing(2);
+        for (index, entry) in self.history.entries.iter().enumerate().rev() {
+            list = list.push(row![
+                button(text(&entry.expression).size(14))
+                    .width(Length::Fill)
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::RecallExpression(index)),
+                button(text(format!("= {}", entry.result)).size(14))
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::RecallResult(index)),
+            ].spacing(2));
+        }
+        scrollable(list).height(Length::Fill).into()
+    }
 }
 
-impl Calculator {
+/* the byte range of the operand at byte offset `at` of `text`: the number or
+   name around it, or the parenthesized group ending there, together with a
+   function name before the group and any `!` after it */
+fn operand_at(text: &str, at: usize) -> Range<usize> {
+    let is_operand = |c: char| c.is_alphanumeric() || c == '.' || c == '_';
+    let run_before = |end: usize| -> usize {
+        end - text[..end].chars().rev().take_while(|c| is_operand(*c)).map(char::len_utf8).sum::<usize>()
+    };
+    let mut start = text[..at].trim_end_matches('!').len();
+    let mut end = at;
+    if text[..start].ends_with(')') {
+        start = run_before(matching_open(text, start - 1).unwrap_or(0));
+    } else {
+        start = run_before(start);
+        end += text[at..].chars().take_while(|c| is_operand(*c)).map(char::len_utf8).sum::<usize>();
+        if text[end..].starts_with('(') {
+            end = matching_close(text, end).map_or(text.len(), |close| close + 1);
+        }
+    }
+    end = text.len() - text[end..].trim_start_matches('!').len();
+    start..end
+}
 
+/* the byte offset of the `(` matching the `)` at `close` */
+fn matching_open(text: &str, close: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text[..close].char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' if depth == 0 => return Some(i),
+            '(' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
 
-    
-    pub async fn calculate(
-        input_string: String,
-    ) -> String {
-        let result_output = Self::evaluate_expr(&input_string).await;
-        match result_output {
-            Ok(result) => result.to_string(),
-            Err(result) => result
-        }       
+/* the byte offset of the `)` matching the `(` at `open` */
+fn matching_close(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices().skip_while(|(i, _)| *i <= open) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
     }
+    None
+}
 
-    async fn evaluate(input: &str, env: &mut HashMap<String, f64>) -> Result<f64, String> {
-        let mut p = parser::Parser::new(input);
-        let ast = p.parse()?;
-        match ast.eval(env) {
-            Some(result) => Ok(result),
-            None => Err("No value for that expression!".to_string())
+/* the operand of an entry of the form `-(...)` whose parentheses enclose everything after the minus */
+fn negated(entry: &str) -> Option<&str> {
+    let inner = entry.strip_prefix("-(")?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => {}
         }
     }
-    
-    async fn evaluate_expr(input_string: &str) -> Result<f64, String> {
-        use std::f64;
-        let mut env = HashMap::new();
-        env.insert("wow".to_string(), 35.0f64);
-        env.insert("pi".to_string(), f64::consts::PI);
-    
-        let mut input = input_string;
-    
-        let expression_text = input.trim_right();
-    
-        let result = Self::evaluate(expression_text, &mut env);
-        match result.await {
-            Ok(value) => {
-                Ok(value)
+    Some(inner)
+}
+
+mod keys {
+    use std::collections::HashMap;
+
+    use iced::keyboard::{self, KeyCode};
+    use iced::{event, Event};
+
+    use crate::Message;
+
+    /* keys that type no character, by their name in the key bindings file */
+    const NAMED_KEYS: [(&str, KeyCode); 27] = [
+        ("Enter", KeyCode::Enter),
+        ("NumpadEnter", KeyCode::NumpadEnter),
+        ("Backspace", KeyCode::Backspace),
+        ("Delete", KeyCode::Delete),
+        ("Escape", KeyCode::Escape),
+        ("Tab", KeyCode::Tab),
+        ("Insert", KeyCode::Insert),
+        ("Home", KeyCode::Home),
+        ("End", KeyCode::End),
+        ("PageUp", KeyCode::PageUp),
+        ("PageDown", KeyCode::PageDown),
+        ("Up", KeyCode::Up),
+        ("Down", KeyCode::Down),
+        ("Left", KeyCode::Left),
+        ("Right", KeyCode::Right),
+        ("F1", KeyCode::F1),
+        ("F2", KeyCode::F2),
+        ("F3", KeyCode::F3),
+        ("F4", KeyCode::F4),
+        ("F5", KeyCode::F5),
+        ("F6", KeyCode::F6),
+        ("F7", KeyCode::F7),
+        ("F8", KeyCode::F8),
+        ("F9", KeyCode::F9),
+        ("F10", KeyCode::F10),
+        ("F11", KeyCode::F11),
+        ("F12", KeyCode::F12),
+    ];
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Key {
+        /* the character a key types, so `+` is the same key on every layout */
+        Char(char),
+        Named(KeyCode),
+    }
+
+    impl Key {
+        pub fn edits_text(&self) -> bool {
+            matches!(self, Key::Named(code) if EDITING_KEYS.contains(code))
+        }
+
+        /* a single character, or one of `NAMED_KEYS` in any case */
+        fn parse(name: &str) -> Option<Key> {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Key::Char(c)),
+                _ => NAMED_KEYS.iter()
+                    .find(|(named, _)| named.eq_ignore_ascii_case(name))
+                    .map(|(_, code)| Key::Named(*code)),
             }
-            Err(s) => {
-                Err(s)
+        }
+    }
+
+    /* keys the expression field keeps for editing while it has the keyboard,
+       unless they are bound */
+    const EDITING_KEYS: [KeyCode; 9] = [
+        KeyCode::Enter,
+        KeyCode::NumpadEnter,
+        KeyCode::Backspace,
+        KeyCode::Delete,
+        KeyCode::Escape,
+        KeyCode::Home,
+        KeyCode::End,
+        KeyCode::Left,
+        KeyCode::Right,
+    ];
+
+    /* a key typed while no widget has the keyboard */
+    pub fn key_event(event: Event, status: event::Status) -> Option<Message> {
+        if status == event::Status::Captured {
+            return None;
+        }
+        key(&event).map(Message::Typing)
+    }
+
+    /* a key typed while the expression field has the keyboard that goes to its
+       binding rather than the field: any bound key, and any that does not edit */
+    pub fn field_key(event: &Event, bindings: &KeyBindings) -> Option<Key> {
+        key(event).filter(|key| bindings.is_bound(*key) || !key.edits_text())
+    }
+
+    /* the key of a keyboard event; Enter, Backspace and the like also send a
+       control character, which is left to their key code */
+    pub fn key(event: &Event) -> Option<Key> {
+        match event {
+            Event::Keyboard(keyboard::Event::CharacterReceived(c)) if !c.is_control() => {
+                Some(Key::Char(*c))
+            },
+            Event::Keyboard(keyboard::Event::KeyPressed { key_code, modifiers })
+                if !(modifiers.control() || modifiers.alt() || modifiers.logo()) => {
+                NAMED_KEYS.iter()
+                    .any(|(_, code)| code == key_code)
+                    .then_some(Key::Named(*key_code))
+            },
+            _ => None,
+        }
+    }
+
+    /* the button a key binding names */
+    fn action(name: &str) -> Option<Message> {
+        Some(match name {
+            "equals" => Message::Equals,
+            "del" => Message::Del,
+            "ce" => Message::CE,
+            "sin" => Message::Sin,
+            "cos" => Message::Cos,
+            "tan" => Message::Tan,
+            "factorial" => Message::Factorial,
+            "negate" => Message::Negate,
+            "add" => Message::Add,
+            "subtract" => Message::Subtract,
+            "multiply" => Message::Multiply,
+            "divide" => Message::Divide,
+            "mod" => Message::Mod,
+            "caret" => Message::Caret,
+            "decimal" => Message::Decimal,
+            "i" => Message::ImaginaryUnit,
+            "mode" => Message::ToggleNumberMode,
+            "style" => Message::ToggleFractionStyle,
+            "complex" => Message::ToggleComplexStyle,
+            "angle" => Message::ToggleAngleUnit,
+            "const" => Message::ToggleConstants,
+            "hist" => Message::ToggleHistory,
+            _ => return None,
+        })
+    }
+
+    /* what each key does; a character without a binding types itself */
+    #[derive(Debug)]
+    pub struct KeyBindings {
+        bindings: HashMap<Key, Message>,
+    }
+
+    impl KeyBindings {
+        pub fn get(&self, key: Key) -> Option<Message> {
+            self.bindings.get(&key).cloned()
+        }
+
+        pub fn is_bound(&self, key: Key) -> bool {
+            self.bindings.contains_key(&key)
+        }
+
+        /* reads `key action` lines, where the action is a button such as
+           `equals` or `sin`, or text in double quotes to type; blank lines
+           and lines starting with `#` are skipped */
+        pub fn load(&mut self, text: &str) -> Result<(), String> {
+            for (n, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (name, binding) = line.split_once(char::is_whitespace)
+                    .ok_or(format!("line {}: expected 'key action'", n + 1))?;
+                let key = Key::parse(name)
+                    .ok_or(format!("line {}: unknown key '{}'", n + 1, name))?;
+                let binding = binding.trim();
+                let message = match binding.strip_prefix('"').and_then(|b| b.strip_suffix('"')) {
+                    Some(text) => Message::Insert(text.to_string()),
+                    None => action(binding).ok_or(format!("line {}: unknown action '{}'", n + 1, binding))?,
+                };
+                self.bindings.insert(key, message);
             }
+            Ok(())
+        }
+    }
+
+    impl Default for KeyBindings {
+        fn default() -> KeyBindings {
+            let bindings = [
+                (Key::Char('0'), Message::Zero),
+                (Key::Char('1'), Message::One),
+                (Key::Char('2'), Message::Two),
+                (Key::Char('3'), Message::Three),
+                (Key::Char('4'), Message::Four),
+                (Key::Char('5'), Message::Five),
+                (Key::Char('6'), Message::Six),
+                (Key::Char('7'), Message::Seven),
+                (Key::Char('8'), Message::Eight),
+                (Key::Char('9'), Message::Nine),
+                (Key::Char('.'), Message::Decimal),
+                (Key::Char('+'), Message::Add),
+                (Key::Char('-'), Message::Subtract),
+                (Key::Char('*'), Message::Multiply),
+                (Key::Char('/'), Message::Divide),
+                (Key::Char('%'), Message::Mod),
+                (Key::Char('^'), Message::Caret),
+                (Key::Char('('), Message::LeftParen),
+                (Key::Char(')'), Message::RightParen),
+                (Key::Char('!'), Message::Factorial),
+                (Key::Char('='), Message::Equals),
+                (Key::Named(KeyCode::Enter), Message::Equals),
+                (Key::Named(KeyCode::NumpadEnter), Message::Equals),
+                (Key::Named(KeyCode::Backspace), Message::Del),
+                (Key::Named(KeyCode::Escape), Message::CE),
+                (Key::Named(KeyCode::Delete), Message::CE),
+            ];
+            KeyBindings { bindings: bindings.into_iter().collect() }
         }
     }
 
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn load(text: &str) -> Result<KeyBindings, String> {
+            let mut keys = KeyBindings::default();
+            keys.load(text).map(|()| keys)
+        }
+
+        #[test]
+        fn load_binds_actions_and_text() {
+            let keys = load("# comment\n\ns sin\nF2 hist\np \"pi\"\nenter del\n").unwrap();
+            assert!(matches!(keys.get(Key::Char('s')), Some(Message::Sin)));
+            assert!(matches!(keys.get(Key::Named(KeyCode::F2)), Some(Message::ToggleHistory)));
+            assert!(matches!(keys.get(Key::Char('p')), Some(Message::Insert(text)) if text == "pi"));
+            // named keys are matched in any case and replace the default
+            assert!(matches!(keys.get(Key::Named(KeyCode::Enter)), Some(Message::Del)));
+            // keys left alone keep their defaults
+            assert!(matches!(keys.get(Key::Char('+')), Some(Message::Add)));
+            assert!(keys.get(Key::Char('q')).is_none());
+        }
+
+        #[test]
+        fn load_reports_the_bad_line() {
+            assert_eq!(load("s sin\nequals").unwrap_err(), "line 2: expected 'key action'");
+            assert_eq!(load("Hyper equals").unwrap_err(), "line 1: unknown key 'Hyper'");
+            assert_eq!(load("\n\nx explode").unwrap_err(), "line 3: unknown action 'explode'");
+        }
+
+        #[test]
+        fn only_named_keys_edit_text() {
+            assert!(Key::Named(KeyCode::Backspace).edits_text());
+            assert!(!Key::Named(KeyCode::F1).edits_text());
+            assert!(!Key::Char('x').edits_text());
+        }
+
+        fn pressed(key_code: KeyCode) -> Event {
+            Event::Keyboard(keyboard::Event::KeyPressed { key_code, modifiers: keyboard::Modifiers::empty() })
+        }
+
+        #[test]
+        fn bound_editing_keys_follow_their_binding_in_the_field() {
+            let keys = KeyBindings::default();
+            let escape = field_key(&pressed(KeyCode::Escape), &keys).unwrap();
+            assert!(matches!(keys.get(escape), Some(Message::CE)));
+            assert_eq!(field_key(&pressed(KeyCode::F1), &keys), Some(Key::Named(KeyCode::F1)));
+            // unbound editing keys are left to the field
+            assert_eq!(field_key(&pressed(KeyCode::Left), &keys), None);
+            let keys = load("Left del").unwrap();
+            assert_eq!(field_key(&pressed(KeyCode::Left), &keys), Some(Key::Named(KeyCode::Left)));
+        }
+    }
 }
 
+mod expression {
+    use iced::widget::text_input::{self, Id};
+    use iced::Command;
+    use iced_native::event::{self, Event};
+    use iced_native::layout::{self, Layout};
+    use iced_native::renderer;
+    use iced_native::widget::operation::Operation;
+    use iced_native::widget::text_input::{cursor, State, TextInput, Value};
+    use iced_native::widget::{self as native, tree, Tree};
+    use iced_native::{mouse, Clipboard, Length, Point, Rectangle, Shell, Widget};
+
+    use crate::keys::{self, KeyBindings};
+    use crate::widget::{Element, Renderer};
+    use crate::Message;
+
+    /* what the text input itself reports, before it becomes a `Message` */
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Changed(String),
+        Submitted,
+    }
+
+    /* the expression as an editable text input that also reports its selection,
+       so the buttons can type at the cursor and wrap the operand under it;
+       characters and bound keys typed into it go through the key bindings like
+       any others */
+    pub struct ExpressionInput<'a> {
+        input: TextInput<'a, Edit, Renderer>,
+        value: &'a str,
+        /* the selection the application last heard of */
+        cursor: (usize, usize),
+        keys: &'a KeyBindings,
+    }
+
+    fn id() -> Id {
+        Id::new("expression")
+    }
+
+    pub fn input<'a>(value: &'a str, cursor: (usize, usize), keys: &'a KeyBindings) -> ExpressionInput<'a> {
+        ExpressionInput {
+            input: TextInput::new("", value, Edit::Changed)
+                .id(id())
+                .padding(10)
+                .on_submit(Edit::Submitted),
+            value,
+            cursor,
+            keys,
+        }
+    }
+
+    /* gives the expression field the keyboard, with the cursor at grapheme `at` */
+    pub fn focus(at: usize) -> Command<Message> {
+        Command::batch([text_input::focus(id()), text_input::move_cursor_to(id(), at)])
+    }
+
+    /* the number of graphemes in `text`, the unit the field counts its cursor in */
+    pub fn graphemes(text: &str) -> usize {
+        Value::new(text).len()
+    }
+
+    /* the byte offset of grapheme `index` of `text` */
+    pub fn byte_offset(text: &str, index: usize) -> usize {
+        Value::new(text).until(index).to_string().len()
+    }
+
+    /* the selection of `state` in `value`, start first */
+    fn selection(state: &State, value: &str) -> (usize, usize) {
+        match state.cursor().state(&Value::new(value)) {
+            cursor::State::Index(at) => (at, at),
+            cursor::State::Selection { start, end } => (start.min(end), start.max(end)),
+        }
+    }
+
+    impl<'a> Widget<Message, Renderer> for ExpressionInput<'a> {
+        fn tag(&self) -> tree::Tag {
+            self.input.tag()
+        }
+
+        fn state(&self) -> tree::State {
+            self.input.state()
+        }
+
+        fn width(&self) -> Length {
+            Widget::<Edit, Renderer>::width(&self.input)
+        }
+
+        fn height(&self) -> Length {
+            Widget::<Edit, Renderer>::height(&self.input)
+        }
+
+        fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+            self.input.layout(renderer, limits)
+        }
+
+        fn operate(&self, tree: &mut Tree, _layout: Layout<'_>, operation: &mut dyn Operation<Message>) {
+            // the same as the text input, which only knows operations on its own messages
+            let state = tree.state.downcast_mut::<State>();
+            let id = native::Id::from(id());
+            operation.focusable(state, Some(&id));
+            operation.text_input(state, Some(&id));
+        }
+
+        fn on_event(
+            &mut self,
+            tree: &mut Tree,
+            event: Event,
+            layout: Layout<'_>,
+            cursor_position: Point,
+            renderer: &Renderer,
+            clipboard: &mut dyn Clipboard,
+            shell: &mut Shell<'_, Message>,
+        ) -> event::Status {
+            if tree.state.downcast_ref::<State>().is_focused() {
+                if let Some(key) = keys::field_key(&event, self.keys) {
+                    shell.publish(Message::Typing(key));
+                    return event::Status::Captured;
+                }
+            }
+
+            let mut edits = Vec::new();
+            let mut input_shell = Shell::new(&mut edits);
+            let status = self.input.on_event(tree, event, layout, cursor_position, renderer, clipboard, &mut input_shell);
+            if input_shell.is_layout_invalid() {
+                shell.invalidate_layout();
+            }
+            if input_shell.are_widgets_invalid() {
+                shell.invalidate_widgets();
+            }
+
+            let mut value = self.value.to_string();
+            for edit in edits {
+                match edit {
+                    Edit::Changed(text) => {
+                        value = text.clone();
+                        shell.publish(Message::Edit(text));
+                    },
+                    Edit::Submitted => shell.publish(Message::Equals),
+                }
+            }
+            let cursor = selection(tree.state.downcast_ref::<State>(), &value);
+            if cursor != self.cursor {
+                self.cursor = cursor;
+                shell.publish(Message::Cursor(cursor.0, cursor.1));
+            }
+            status
+        }
+
+        fn draw(
+            &self,
+            tree: &Tree,
+            renderer: &mut Renderer,
+            theme: &crate::theme::Theme,
+            style: &renderer::Style,
+            layout: Layout<'_>,
+            cursor_position: Point,
+            viewport: &Rectangle,
+        ) {
+            Widget::<Edit, Renderer>::draw(&self.input, tree, renderer, theme, style, layout, cursor_position, viewport)
+        }
+
+        fn mouse_interaction(
+            &self,
+            tree: &Tree,
+            layout: Layout<'_>,
+            cursor_position: Point,
+            viewport: &Rectangle,
+            renderer: &Renderer,
+        ) -> mouse::Interaction {
+            self.input.mouse_interaction(tree, layout, cursor_position, viewport, renderer)
+        }
+    }
+
+    impl<'a> From<ExpressionInput<'a>> for Element<'a, Message> {
+        fn from(input: ExpressionInput<'a>) -> Element<'a, Message> {
+            Element::new(input)
+        }
+    }
+}
 
 mod widget {
     #![allow(dead_code)]
@@ -515,8 +1236,8 @@ mod widget {
 }
 
 mod theme {
-    use iced::widget::{button, container, text, row};
-    use iced::{application, color, Color};
+    use iced::widget::{button, container, scrollable, text, text_input};
+    use iced::{application, color};
 
     #[derive(Debug, Clone, Copy, Default)]
     pub struct Theme;
@@ -565,6 +1286,67 @@ mod theme {
         }
     }
 
+    impl scrollable::StyleSheet for Theme {
+        type Style = ();
+
+        fn active(&self, _style: &Self::Style) -> scrollable::Scrollbar {
+            scrollable::Scrollbar {
+                background: None,
+                border_radius: 2.0,
+                border_width: 0.0,
+                border_color: color!(0x45, 0x85, 0x88),
+                scroller: scrollable::Scroller {
+                    color: color!(0x50, 0x49, 0x45),
+                    border_radius: 2.0,
+                    border_width: 0.0,
+                    border_color: color!(0x45, 0x85, 0x88),
+                },
+            }
+        }
+
+        fn hovered(&self, style: &Self::Style) -> scrollable::Scrollbar {
+            scrollable::Scrollbar {
+                scroller: scrollable::Scroller {
+                    color: color!(0x66, 0x5c, 0x54),
+                    ..self.active(style).scroller
+                },
+                ..self.active(style)
+            }
+        }
+    }
+
+    impl text_input::StyleSheet for Theme {
+        type Style = ();
+
+        fn active(&self, _style: &Self::Style) -> text_input::Appearance {
+            text_input::Appearance {
+                background: color!(0x28, 0x28, 0x28).into(),
+                border_radius: 4.0,
+                border_width: 1.0,
+                border_color: color!(0x45, 0x85, 0x88),
+            }
+        }
+
+        fn focused(&self, style: &Self::Style) -> text_input::Appearance {
+            text_input::Appearance {
+                border_color: color!(0x83, 0xa5, 0x98),
+                ..self.active(style)
+            }
+        }
+
+        fn placeholder_color(&self, _style: &Self::Style) -> iced::Color {
+            color!(0x92, 0x83, 0x74)
+        }
+
+        fn value_color(&self, _style: &Self::Style) -> iced::Color {
+            color!(0xeb, 0xdb, 0xb2)
+        }
+
+        fn selection_color(&self, _style: &Self::Style) -> iced::Color {
+            color!(0x45, 0x85, 0x88)
+        }
+    }
+
     #[derive(Debug, Clone, Copy, Default)]
     pub enum Button {
         #[default]
@@ -593,3 +1375,126 @@ mod theme {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* a calculator showing `text` with the cursor at grapheme `at` */
+    fn gui(text: &str, at: usize) -> CalculatorGUI {
+        CalculatorGUI {
+            display_text: text.to_string(),
+            cursor: (at, at),
+            error: None,
+            done_calculation: false,
+            settings: CalcSettings::default(),
+            int_value: 0,
+            constants: Vec::new(),
+            show_constants: false,
+            history: History::default(),
+            show_history: false,
+            keys: KeyBindings::default(),
+            env: Environment::new(),
+        }
+    }
+
+    fn operand(text: &str, at: usize) -> &str {
+        &text[operand_at(text, at)]
+    }
+
+    #[test]
+    fn operand_at_numbers_and_names() {
+        assert_eq!(operand("1 + 23", 6), "23");
+        assert_eq!(operand("1 + 23", 5), "23");
+        assert_eq!(operand("1 + 23", 4), "23");
+        assert_eq!(operand("2.5 * x_1", 3), "2.5");
+        assert_eq!(operand("1 + ", 4), "");
+        assert_eq!(operand("", 0), "");
+    }
+
+    #[test]
+    fn operand_at_groups_calls_and_factorials() {
+        assert_eq!(operand("2 * (1 + 3)", 11), "(1 + 3)");
+        assert_eq!(operand("1 + sin(x)", 10), "sin(x)");
+        assert_eq!(operand("1 + sin(x)", 4), "sin(x)");
+        assert_eq!(operand("1 + max(a, (b))", 15), "max(a, (b))");
+        assert_eq!(operand("2 * 5!", 6), "5!");
+        assert_eq!(operand("2 * (1 + 3)!!", 13), "(1 + 3)!!");
+        // an unbalanced group runs to the end
+        assert_eq!(operand("1 + sin(x", 4), "sin(x");
+    }
+
+    #[test]
+    fn operand_at_counts_bytes() {
+        assert_eq!(operand("π × 2", "π × 2".len()), "2");
+        assert_eq!(operand("2 × π", "2 × π".len()), "π");
+    }
+
+    #[test]
+    fn functions_wrap_the_operand_at_the_cursor() {
+        let mut calc = gui("1 + 23", 6);
+        let _ = calc.update(Message::Sin);
+        assert_eq!(calc.display_text, "1 + sin(23)");
+        assert_eq!(calc.cursor, (11, 11));
+
+        let mut calc = gui("1 + ", 4);
+        let _ = calc.update(Message::Cos);
+        assert_eq!(calc.display_text, "1 + cos()");
+        // inside the parentheses, ready for the argument
+        assert_eq!(calc.cursor, (8, 8));
+
+        let mut calc = gui("2 * 5", 5);
+        let _ = calc.update(Message::Factorial);
+        assert_eq!(calc.display_text, "2 * 5!");
+    }
+
+    #[test]
+    fn functions_wrap_the_selection_or_a_result() {
+        let mut calc = gui("1 + 2 * 3", 0);
+        calc.cursor = (4, 9);
+        let _ = calc.update(Message::Tan);
+        assert_eq!(calc.display_text, "1 + tan(2 * 3)");
+
+        let mut calc = gui("1 + 2", 2);
+        calc.done_calculation = true;
+        let _ = calc.update(Message::Sin);
+        assert_eq!(calc.display_text, "sin(1 + 2)");
+        assert!(!calc.done_calculation);
+    }
+
+    #[test]
+    fn wrap_puts_text_around_a_range() {
+        let mut calc = gui("ab", 0);
+        let _ = calc.wrap(1..2, "(", ")");
+        assert_eq!(calc.display_text, "a(b)");
+        assert_eq!(calc.cursor, (4, 4));
+        let _ = calc.wrap(4..4, "√(", ")");
+        assert_eq!(calc.display_text, "a(b)√()");
+        assert_eq!(calc.cursor, (6, 6));
+    }
+
+    #[test]
+    fn fetched_rates_stay_for_later_calculations() {
+        let mut calc = gui("", 0);
+        let rates = Rates {
+            base: "EUR".to_string(),
+            date: "2024-01-02".to_string(),
+            rates: [("USD".to_string(), 1.25)].into_iter().collect(),
+        };
+        let _ = calc.update(Message::RatesLoaded(Ok(rates)));
+        let mut env = calc.env.clone();
+        let value = basic_calculator::eval("100 USD to EUR", &mut env).unwrap();
+        assert_eq!(value.format(&calc.settings), "80 EUR");
+    }
+
+    #[test]
+    fn escape_clears_the_focused_field() {
+        let mut calc = gui("1 + 2", 5);
+        let escape = iced::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+            key_code: iced::keyboard::KeyCode::Escape,
+            modifiers: iced::keyboard::Modifiers::empty(),
+        });
+        let key = keys::field_key(&escape, &calc.keys).unwrap();
+        let _ = calc.update(Message::Typing(key));
+        assert_eq!(calc.display_text, "");
+    }
+}
@@ -1,261 +1,185 @@
-extern crate std;
-use std::collections::HashMap;
-use std::f64;
-
-pub trait Node {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64>;
-}
-
-pub struct Num {
-    pub num: f64
-}
-
-impl Node for Num {
-    fn eval(&self, _env: &mut HashMap<String, f64>) -> Option<f64> {
-        Some(self.num)
-    }
-}
-
-pub struct Add {
-    pub left: Box<dyn Node>,
-    pub right: Box<dyn Node>,
-}
-
-impl Node for Add {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.left.eval(env) {
-            Some(l) => {
-                match self.right.eval(env) {
-                    Some(r) => Some(l + r),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Sub {
-    pub left: Box<dyn Node>,
-    pub right: Box<dyn Node>,
-}
-
-impl Node for Sub {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.left.eval(env) {
-            Some(l) => {
-                match self.right.eval(env) {
-                    Some(r) => Some(l - r),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Mul {
-    pub left: Box<dyn Node>,
-    pub right: Box<dyn Node>,
-}
-
-impl Node for Mul {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.left.eval(env) {
-            Some(l) => {
-                match self.right.eval(env) {
-                    Some(r) => Some(l * r),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Div {
-    pub left: Box<dyn Node>,
-    pub right: Box<dyn Node>,
-}
-
-impl Node for Div {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.left.eval(env) {
-            Some(l) => {
-                match self.right.eval(env) {
-                    Some(r) => Some(l / r),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Mod {
-    pub left: Box<dyn Node>,
-    pub right: Box<dyn Node>,
-}
-
-impl Node for Mod {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.left.eval(env) {
-            Some(l) => {
-                match self.right.eval(env) {
-                    Some(r) => Some(l % r),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Pow {
-    pub base: Box<dyn Node>,
-    pub exponent: Box<dyn Node>
-}
-
-impl Node for Pow {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.base.eval(env) {
-            Some(b) => {
-                match self.exponent.eval(env) {
-                    Some(e) => Some(b.powf(e)),
-                    None => None
-                }
-            }
-            None => None
-        }
-    }
-}
-
-pub struct Sin {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Sin {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.arg.eval(env) {
-            Some(x) => Some(x.sin()),
-            None => None
-        }
-    }
-}
-pub struct Cos {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Cos {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.arg.eval(env) {
-            Some(x) => Some(x.cos()),
-            None => None
-        }
-    }
-}
-
-pub struct Tan {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Tan {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.arg.eval(env) {
-            Some(x) => Some(x.tan()),
-            None => None
-        }
-    }
-}
-
-pub struct Factorial {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Factorial {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.arg.eval(env) {
-            Some(x) => Some(factorial(x)),
-            None => None
+use crate::parser::settings::AngleUnit;
+use crate::parser::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    pub fn is_comparison(&self) -> bool {
+        matches!(*self, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge)
+    }
+
+    pub fn is_bitwise(&self) -> bool {
+        matches!(*self, BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr)
+    }
+
+    /* the operator as written, for error messages */
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Pow => "^",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "xor",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
         }
     }
 }
 
-fn factorial(number: f64) -> f64 {
-    if number < 2.0 {
-        1.0
-    } else {
-        number * factorial(number - 1.0)
-    }
-}
-
-pub struct Sqrt {
-    pub arg: Box<dyn Node>
-}
-impl Node for Sqrt {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match self.arg.eval(env) {
-            Some(x) => Some(x.sqrt()),
-            None => None
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    BitNot,
+    Not,
+    /* postfix `!`, `!!` and `%` */
+    Factorial,
+    DoubleFactorial,
+    Percent,
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Not => "not",
+            UnaryOp::Factorial => "!",
+            UnaryOp::DoubleFactorial => "!!",
+            UnaryOp::Percent => "%",
         }
     }
 }
 
-pub struct Print {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Print {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        let res = self.arg.eval(env);
-        match res {
-            Some(x) => println!("{}", x),
-            None => {}
+/* `and` and `or`, which only evaluate their right side when needed */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num {
+        /* literal text, read in the representation of the current number mode */
+        literal: String,
+        span: Span,
+    },
+    Var {
+        name: String,
+        span: Span,
+    },
+    /* a name read as a unit whatever else it may mean: the `h` of `5 h` or
+       the target of `to h`, where a bare `h` is Planck's constant */
+    Unit {
+        name: String,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        arg: Box<Expr>,
+        span: Span,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    Logical {
+        op: LogicalOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    /* `if(cond, then, otherwise)` or `cond ? then : otherwise` */
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+        span: Span,
+    },
+    /* `30deg`: an angle in an explicit unit, converted to the current angle mode */
+    Angle {
+        arg: Box<Expr>,
+        unit: AngleUnit,
+        span: Span,
+    },
+    /* `60 mph to m/s`: a quantity expressed in the unit of `target` */
+    Convert {
+        arg: Box<Expr>,
+        target: Box<Expr>,
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    Assign {
+        name: String,
+        value: Box<Expr>,
+        span: Span,
+    },
+    FnDef {
+        name: String,
+        params: Vec<String>,
+        body: Box<Expr>,
+        span: Span,
+    },
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match *self {
+            Expr::Num { span, .. }
+            | Expr::Var { span, .. }
+            | Expr::Unit { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Logical { span, .. }
+            | Expr::If { span, .. }
+            | Expr::Angle { span, .. }
+            | Expr::Convert { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::FnDef { span, .. } => span,
         }
-        res
-    }
-}
-
-pub struct Negate {
-    pub arg: Box<dyn Node>
-}
-
-impl Node for Negate {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        let res = self.arg.eval(env);
-        match res {
-            Some(x) => Some(-x),
-            None => None
-        };
-        res
     }
 }
 
-pub struct Var {
-    pub name: String
-}
-
-impl Node for Var {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        match env.get(&(self.name)[..]) {
-            Some(r) => Some(*r),
-            None => None
-        }
-    }
-}
-
-pub struct Assignment {
-    pub name: String,
-    pub value: Box<dyn Node>
-}
-
-impl Node for Assignment {
-    fn eval(&self, env: &mut HashMap<String, f64>) -> Option<f64> {
-        let val = self.value.eval(env);
-        match val {
-            Some(x) => { env.insert(self.name.clone(), x); }
-            None => {}
-        }
-        val
-    }
+/* statements separated by `;` or newlines, evaluated in order */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub statements: Vec<Expr>,
 }
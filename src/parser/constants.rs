@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+
+use crate::parser::error::EvalError;
+use crate::parser::settings::Settings;
+use crate::parser::value::{self, Value};
+
+/* names the parser gives a meaning of their own */
+const RESERVED: [&str; 11] = ["let", "and", "or", "not", "if", "xor", "to", "in", "true", "false", "i"];
+
+/* name, value and description of the built-in constants; the mathematical ones
+   have enough digits for the default decimal precision, the physical ones are
+   CODATA 2018 values in SI units */
+const BUILTIN: [(&str, &str, &str); 19] = [
+    ("pi", "3.14159265358979323846264338327950288419716939937510582", "ratio of a circle's circumference to its diameter"),
+    ("e", "2.71828182845904523536028747135266249775724709369995957", "base of the natural logarithm"),
+    ("tau", "6.28318530717958647692528676655900576839433879875021164", "2 pi, one full turn in radians"),
+    ("phi", "1.61803398874989484820458683436563811772030917980576286", "golden ratio"),
+    ("gamma_e", "0.57721566490153286060651209008240243104215933593992359", "Euler-Mascheroni constant"),
+    ("c", "299792458", "speed of light in vacuum, m/s"),
+    ("h", "6.62607015e-34", "Planck constant, J s"),
+    ("hbar", "1.054571817e-34", "reduced Planck constant, J s"),
+    ("NA", "6.02214076e23", "Avogadro constant, 1/mol"),
+    ("kB", "1.380649e-23", "Boltzmann constant, J/K"),
+    ("R", "8.314462618", "molar gas constant, J/(mol K)"),
+    ("G", "6.67430e-11", "Newtonian constant of gravitation, m^3/(kg s^2)"),
+    ("g", "9.80665", "standard acceleration of gravity, m/s^2"),
+    ("qe", "1.602176634e-19", "elementary charge, C"),
+    ("me", "9.1093837015e-31", "electron mass, kg"),
+    ("mp", "1.67262192369e-27", "proton mass, kg"),
+    ("eps0", "8.8541878128e-12", "vacuum electric permittivity, F/m"),
+    ("mu0", "1.25663706212e-6", "vacuum magnetic permeability, N/A^2"),
+    ("sigma", "5.670374419e-8", "Stefan-Boltzmann constant, W/(m^2 K^4)"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub name: String,
+    /* kept as text so every number mode reads it at its own precision */
+    pub literal: String,
+    pub description: String,
+}
+
+impl Constant {
+    pub fn value(&self, settings: &Settings) -> Result<Value, EvalError> {
+        Value::from_literal(&self.literal, settings)
+    }
+}
+
+/* read-only named values; `let` cannot assign to any of these names */
+#[derive(Debug, Clone)]
+pub struct Constants {
+    table: BTreeMap<String, Constant>,
+}
+
+impl Constants {
+    pub fn new() -> Constants {
+        Constants { table: BTreeMap::new() }
+    }
+
+    /* adds or replaces a constant; the literal is any number the lexer reads,
+       optionally negative, such as `-1.5e3` or `1_000` */
+    pub fn define(&mut self, name: &str, literal: &str, description: &str) -> Result<(), String> {
+        check_name(name)?;
+        let literal = literal.replace('_', "");
+        match BigDecimal::from_str(&literal) {
+            Ok(d) if value::decimal_to_rational(&d).is_ok() => {}
+            _ => return Err(format!("invalid value '{}' for constant '{}'", literal, name)),
+        }
+        self.table.insert(name.to_string(), Constant {
+            name: name.to_string(),
+            literal,
+            description: description.to_string(),
+        });
+        Ok(())
+    }
+
+    /* reads the definitions of the user's constants file */
+    pub fn load(&mut self, text: &str) -> Result<(), String> {
+        for (line, name, literal, description) in definitions(text)? {
+            self.define(name, literal, description).map_err(|e| format!("line {}: {}", line, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Constant> {
+        self.table.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.table.contains_key(name)
+    }
+
+    /* every constant, sorted by name */
+    pub fn iter(&self) -> impl Iterator<Item = &Constant> {
+        self.table.values()
+    }
+}
+
+/* the line number, name, value and description of every `name = value  # description`
+   line of a constants or units file; blank lines and lines starting with `#` are skipped */
+pub fn definitions(text: &str) -> Result<Vec<(usize, &str, &str, &str)>, String> {
+    let mut defs = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (definition, description) = line.split_once('#').unwrap_or((line, ""));
+        let (name, value) = definition.split_once('=')
+            .ok_or(format!("line {}: expected 'name = value'", n + 1))?;
+        check_name(name.trim()).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        defs.push((n + 1, name.trim(), value.trim(), description.trim()));
+    }
+    Ok(defs)
+}
+
+/* user constants and units need a name the parser reads as a plain symbol */
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("invalid name '{}'", name));
+    }
+    if RESERVED.contains(&name) {
+        return Err(format!("'{}' is reserved", name));
+    }
+    Ok(())
+}
+
+impl Default for Constants {
+    fn default() -> Constants {
+        let mut constants = Constants::new();
+        for (name, literal, description) in BUILTIN {
+            constants.table.insert(name.to_string(), Constant {
+                name: name.to_string(),
+                literal: literal.to_string(),
+                description: description.to_string(),
+            });
+        }
+        constants
+    }
+}
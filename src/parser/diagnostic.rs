@@ -0,0 +1,58 @@
+use crate::parser::span::Span;
+
+/* renders `message` followed by the offending source line with a ^~~~ underline
+   beneath `span`, e.g.
+
+   error: unknown variable 'foo'
+     2 * foo + 1
+         ^~~
+*/
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let start = span.start.min(chars.len());
+    let end = span.end.clamp(start, chars.len());
+
+    let line_start = chars[..start].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+    let line_end = chars[start..].iter().position(|&c| c == '\n').map_or(chars.len(), |i| start + i);
+    let line: String = chars[line_start..line_end].iter().collect();
+
+    let column = start - line_start;
+    let width = end.min(line_end).saturating_sub(start).max(1);
+    let mut underline = " ".repeat(column);
+    underline.push('^');
+    underline.push_str(&"~".repeat(width - 1));
+
+    format!("error: {}\n  {}\n  {}", message, line, underline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underlines_the_span() {
+        assert_eq!(render("2 * foo + 1", Span::new(4, 7), "unknown variable 'foo'"),
+            "error: unknown variable 'foo'\n  2 * foo + 1\n      ^~~");
+        assert_eq!(render("1 + 2 / 0", Span::new(8, 9), "division by zero"),
+            "error: division by zero\n  1 + 2 / 0\n          ^");
+    }
+
+    #[test]
+    fn shows_only_the_line_of_the_span() {
+        let source = "let x = 1\nx + y\nx * 2";
+        assert_eq!(render(source, Span::new(14, 15), "unknown variable 'y'"),
+            "error: unknown variable 'y'\n  x + y\n      ^");
+    }
+
+    #[test]
+    fn clips_spans_to_the_line() {
+        // a span across a newline is underlined to the end of its first line
+        assert_eq!(render("(1 +\n2", Span::new(0, 6), "expected ')'"),
+            "error: expected ')'\n  (1 +\n  ^~~~");
+        // at the end of the input there is still a caret to point with
+        assert_eq!(render("2 *", Span::new(3, 3), "expected an operand"),
+            "error: expected an operand\n  2 *\n     ^");
+        assert_eq!(render("2 *", Span::new(10, 12), "expected an operand"),
+            "error: expected an operand\n  2 *\n     ^");
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use crate::parser::ast::Expr;
+use crate::parser::constants::{self, Constants};
+use crate::parser::error::EvalError;
+use crate::parser::functions::Registry;
+use crate::parser::settings::Settings;
+use crate::parser::units::Units;
+use crate::parser::value::Value;
+
+/* deepest nesting of user function calls before evaluation gives up */
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/* deepest nesting of subexpressions, counting those in the bodies of called
+   functions, that evaluation follows before giving up; this fits the 2 MiB
+   stack of a spawned thread even in debug builds */
+pub const MAX_EVAL_DEPTH: usize = 400;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+/* variables and callable functions visible to an evaluation */
+#[derive(Clone, Default)]
+pub struct Environment {
+    pub vars: HashMap<String, Value>,
+    pub constants: Constants,
+    pub units: Units,
+    pub functions: Registry,
+    pub user_functions: HashMap<String, UserFunction>,
+    pub settings: Settings,
+    frames: Vec<HashMap<String, Value>>,
+    nesting: usize,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    /* parameters of the innermost call shadow constants and globals;
+       callers' locals are not visible. A constant can fail to read in the
+       current mode, as pi does in integer mode */
+    pub fn get(&self, name: &str) -> Option<Result<Value, EvalError>> {
+        if let Some(frame) = self.frames.last() {
+            if let Some(v) = frame.get(name) {
+                return Some(Ok(v.clone()));
+            }
+        }
+        if let Some(c) = self.constants.get(name) {
+            return Some(c.value(&self.settings));
+        }
+        self.vars.get(name).cloned().map(Ok)
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.insert(name.to_string(), value),
+            None => self.vars.insert(name.to_string(), value),
+        };
+    }
+
+    pub fn define(&mut self, name: &str, params: Vec<String>, body: Expr) {
+        self.user_functions.insert(name.to_string(), UserFunction { params, body });
+    }
+
+    /* adds the units of a units file, whose definitions are quantities such as
+       `furlong = 201.168 m`; each may use the units defined before it */
+    pub fn load_units(&mut self, text: &str) -> Result<(), String> {
+        for (line, name, definition, description) in constants::definitions(text)? {
+            let q = match crate::eval(definition, self) {
+                Ok(Value::Quantity(q)) => q,
+                Ok(_) => return Err(format!("line {}: '{}' has no unit", line, definition)),
+                Err(e) => return Err(format!("line {}: {}", line, e)),
+            };
+            self.units.define(name, q.value * q.unit.factor(), q.unit.dim(), description);
+        }
+        Ok(())
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn push_frame(&mut self, frame: HashMap<String, Value>) {
+        self.frames.push(frame);
+    }
+
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    /* counts one more subexpression under evaluation, or fails past `MAX_EVAL_DEPTH` */
+    pub fn enter(&mut self) -> Result<(), EvalError> {
+        if self.nesting >= MAX_EVAL_DEPTH {
+            return Err(EvalError::NestingLimit(MAX_EVAL_DEPTH));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.nesting -= 1;
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::parser::diagnostic;
+use crate::parser::functions::Arity;
+use crate::parser::span::{Span, Spanned};
+use crate::parser::value::Value;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    UnknownVariable(String),
+    UnknownFunction(String),
+    ArityMismatch { function: String, expected: Arity, found: usize },
+    DomainError { function: String, arg: f64 },
+    /* a function or operator that only works on real numbers got a complex one */
+    ComplexArgument(String),
+    /* arithmetic on the true/false result of a comparison */
+    BooleanArgument(String),
+    /* a sum, remainder or comparison of quantities whose dimensions differ */
+    IncompatibleUnits(String, String),
+    /* a function or operator that only works on plain numbers got a quantity with units */
+    UnitArgument(String),
+    /* the right side of `to` is not a unit */
+    ConversionTarget,
+    /* `let` on a name from the constants table */
+    ConstantAssignment(String),
+    DivisionByZero,
+    Overflow,
+    RecursionLimit(usize),
+    /* an expression whose subexpressions nest deeper than evaluation can follow */
+    NestingLimit(usize),
+    /* a currency code used before exchange rates were loaded */
+    RatesNotLoaded(String),
+    /* a fraction in integer mode, which has no way to hold it */
+    NotAnInteger(String),
+    /* an exponent too large to raise, or a literal too large to expand, exactly */
+    ExponentLimit(i64),
+}
+
+pub type EvalResult = Result<Value, Spanned<EvalError>>;
+
+impl EvalError {
+    pub fn at(self, span: Span) -> Spanned<EvalError> {
+        Spanned::new(self, span)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnknownVariable(name) => write!(f, "unknown variable '{}'", name),
+            EvalError::UnknownFunction(name) => write!(f, "unknown function '{}'", name),
+            EvalError::ArityMismatch { function, expected, found } => {
+                write!(f, "{} expects {} argument(s) but got {}", function, expected, found)
+            }
+            EvalError::DomainError { function, arg } => {
+                write!(f, "{}({}) is undefined", function, arg)
+            }
+            EvalError::ComplexArgument(function) => {
+                write!(f, "{} is not defined for complex numbers", function)
+            }
+            EvalError::BooleanArgument(function) => {
+                write!(f, "{} is not defined for booleans", function)
+            }
+            EvalError::IncompatibleUnits(l, r) => write!(f, "incompatible units: {} and {}", l, r),
+            EvalError::UnitArgument(function) => {
+                write!(f, "{} is not defined for quantities with units", function)
+            }
+            EvalError::ConversionTarget => write!(f, "the target of a conversion must be a unit"),
+            EvalError::ConstantAssignment(name) => {
+                write!(f, "cannot assign to constant '{}'", name)
+            }
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "result is too large"),
+            EvalError::RecursionLimit(depth) => {
+                write!(f, "maximum call depth of {} exceeded", depth)
+            }
+            EvalError::NestingLimit(depth) => {
+                write!(f, "expression is nested more than {} levels deep", depth)
+            }
+            EvalError::RatesNotLoaded(code) => {
+                write!(f, "no exchange rate loaded for '{}'", code)
+            }
+            EvalError::NotAnInteger(x) => write!(f, "{} is not an integer", x),
+            EvalError::ExponentLimit(limit) => {
+                write!(f, "exponent is beyond the exact limit of {}", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Spanned<EvalError> {
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span, &self.node.to_string())
+    }
+}
+
+/* turns a non-finite result of finite operands into an error */
+pub fn check_overflow(result: f64, operands: &[f64]) -> Result<f64, EvalError> {
+    if result.is_infinite() && operands.iter().all(|x| x.is_finite()) {
+        Err(EvalError::Overflow)
+    } else {
+        Ok(result)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: String, span: Span) -> ParseError {
+        ParseError { message, span }
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span, &self.message)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.span.start)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/* any failure from turning source text into a value */
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    Parse(ParseError),
+    Eval(Spanned<EvalError>),
+}
+
+impl Error {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::Parse(e) => e.span,
+            Error::Eval(e) => e.span,
+        }
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            Error::Parse(e) => e.render(source),
+            Error::Eval(e) => e.render(source),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<Spanned<EvalError>> for Error {
+    fn from(e: Spanned<EvalError>) -> Error {
+        Error::Eval(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Eval(e) => write!(f, "{} at position {}", e.node, e.span.start),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,318 @@
+use crate::parser::ast::{BinOp, Expr, LogicalOp, Program, UnaryOp};
+use std::collections::HashMap;
+
+use num_complex::Complex64;
+
+use crate::parser::env::{Environment, UserFunction, MAX_CALL_DEPTH};
+use crate::parser::error::{EvalError, EvalResult};
+use crate::parser::functions::{self, Arity};
+use crate::parser::settings::{AngleUnit, Settings};
+use crate::parser::span::Span;
+use crate::parser::units::{self, Quantity};
+use crate::parser::value::Value;
+
+impl Expr {
+    pub fn eval(&self, env: &mut Environment) -> EvalResult {
+        env.enter().map_err(|e| e.at(self.span()))?;
+        let res = self.eval_node(env);
+        env.leave();
+        res
+    }
+
+    /* each variant with more than a line of work has a function of its own, which
+       keeps the stack frame of every level of a deeply nested expression small */
+    fn eval_node(&self, env: &mut Environment) -> EvalResult {
+        match self {
+            Expr::Num { literal, span } => Value::from_literal(literal, &env.settings).map_err(|e| e.at(*span)),
+            Expr::Var { name, span } => var(name, *span, env),
+            Expr::Unit { name, span } => {
+                env.units.value(name).ok_or_else(|| EvalError::UnknownVariable(name.clone()).at(*span))
+            }
+            Expr::Unary { op, arg, span } => unary(*op, arg, *span, env),
+            Expr::Logical { op, lhs, rhs, .. } => logical(*op, lhs, rhs, env),
+            Expr::If { cond, then, otherwise, .. } => {
+                if cond.eval(env)?.truthy() { then.eval(env) } else { otherwise.eval(env) }
+            }
+            Expr::Binary { op, lhs, rhs, span } => binary(*op, lhs, rhs, *span, env),
+            Expr::Angle { arg, unit, span } => angle(arg, *unit, *span, env),
+            Expr::Convert { arg, target, span } => convert(arg, target, *span, env),
+            Expr::Call { name, args, span } => call(name, args, *span, env),
+            Expr::Assign { name, value, span } => assign(name, value, *span, env),
+            Expr::FnDef { name, params, body, .. } => {
+                env.define(name, params.clone(), (**body).clone());
+                Ok(Value::from_integer(0, &env.settings))
+            }
+        }
+    }
+}
+
+fn var(name: &str, span: Span, env: &mut Environment) -> EvalResult {
+    match env.get(name) {
+        Some(r) => r.map_err(|e| e.at(span)),
+        // names that are neither variables nor constants may be units
+        None if env.units.get(name).is_some() => Ok(env.units.value(name).unwrap()),
+        // `i` is the imaginary unit unless the user assigned it
+        None if name == "i" => Ok(Value::Complex(Complex64::i())),
+        None if name == "true" || name == "false" => Ok(Value::Bool(name == "true")),
+        None if units::CURRENCY_CODES.contains(&name) => {
+            Err(EvalError::RatesNotLoaded(name.to_string()).at(span))
+        }
+        None => Err(EvalError::UnknownVariable(name.to_string()).at(span)),
+    }
+}
+
+fn unary(op: UnaryOp, arg: &Expr, span: Span, env: &mut Environment) -> EvalResult {
+    let v = arg.eval(env)?;
+    match op {
+        UnaryOp::Not => return Ok(Value::Bool(!v.truthy())),
+        _ if v.is_bool() => Err(EvalError::BooleanArgument(op.symbol().to_string())),
+        UnaryOp::BitNot | UnaryOp::Factorial | UnaryOp::DoubleFactorial if v.is_quantity() => {
+            Err(EvalError::UnitArgument(op.symbol().to_string()))
+        }
+        UnaryOp::Neg => Ok((-v).wrapped(&env.settings)),
+        UnaryOp::Plus => Ok(v),
+        UnaryOp::BitNot => v.to_integral("~").map(|n| Value::from_integer(!n, &env.settings)),
+        UnaryOp::Factorial => functions::factorial(&v, &env.settings),
+        UnaryOp::DoubleFactorial => functions::double_factorial(&v, &env.settings),
+        UnaryOp::Percent => {
+            let hundred = Value::from_integer(100, &env.settings);
+            Value::binary(BinOp::Div, v, hundred, &env.settings)
+        }
+    }.map_err(|e| e.at(span))
+}
+
+// the right operand is only evaluated when it can change the answer
+fn logical(op: LogicalOp, lhs: &Expr, rhs: &Expr, env: &mut Environment) -> EvalResult {
+    let l = lhs.eval(env)?.truthy();
+    match op {
+        LogicalOp::And if !l => Ok(Value::Bool(false)),
+        LogicalOp::Or if l => Ok(Value::Bool(true)),
+        _ => Ok(Value::Bool(rhs.eval(env)?.truthy())),
+    }
+}
+
+/* a chain such as 1 + 2 + 3 nests to the left, so it is applied in a loop
+   rather than by recursion, however long it is */
+fn binary(op: BinOp, lhs: &Expr, rhs: &Expr, span: Span, env: &mut Environment) -> EvalResult {
+    let mut chain = vec![(op, rhs, span)];
+    let mut first = lhs;
+    while let Expr::Binary { op, lhs, rhs, span } = first {
+        chain.push((*op, &**rhs, *span));
+        first = &**lhs;
+    }
+    let mut l = first.eval(env)?;
+    for (op, rhs, span) in chain.into_iter().rev() {
+        let r = rhs.eval(env)?;
+        l = apply(op, l, r, rhs, span, &env.settings)?;
+    }
+    Ok(l)
+}
+
+fn apply(op: BinOp, l: Value, r: Value, rhs: &Expr, span: Span, settings: &Settings) -> EvalResult {
+    match op {
+        // `a + b%` and `a - b%` add or take away b percent of a, so 200 + 10% is 220
+        BinOp::Add | BinOp::Sub if matches!(rhs, Expr::Unary { op: UnaryOp::Percent, .. }) => {
+            return Value::binary(BinOp::Mul, l.clone(), r, settings)
+                .and_then(|part| Value::binary(op, l, part, settings))
+                .map_err(|e| e.at(span));
+        }
+        BinOp::Div | BinOp::Mod if r.is_zero() => {
+            return Err(EvalError::DivisionByZero.at(rhs.span()));
+        }
+        _ => {}
+    }
+    Value::binary(op, l, r, settings).map_err(|e| e.at(span))
+}
+
+fn angle(arg: &Expr, unit: AngleUnit, span: Span, env: &mut Environment) -> EvalResult {
+    let v = arg.eval(env)?;
+    if v.is_bool() {
+        return Err(EvalError::BooleanArgument(unit.suffix().to_string()).at(span));
+    }
+    if v.is_quantity() {
+        return Err(EvalError::UnitArgument(unit.suffix().to_string()).at(span));
+    }
+    if unit == env.settings.angle {
+        return Ok(v);
+    }
+    if v.is_complex() {
+        return Err(EvalError::ComplexArgument(unit.suffix().to_string()).at(span));
+    }
+    let x = v.to_f64() * env.settings.angle.turn() / unit.turn();
+    Value::from_f64(x, &env.settings).map_err(|e| e.at(span))
+}
+
+fn convert(arg: &Expr, target: &Expr, span: Span, env: &mut Environment) -> EvalResult {
+    let v = arg.eval(env)?;
+    let unit = match target.eval(env)? {
+        Value::Quantity(q) if q.value == 1.0 => q.unit,
+        _ => return Err(EvalError::ConversionTarget.at(target.span())),
+    };
+    let value = Quantity::of(v)
+        .and_then(|q| q.convert(&unit))
+        .map_err(|e| e.at(span))?;
+    Ok(Value::Quantity(Quantity { value, unit }))
+}
+
+fn assign(name: &str, value: &Expr, span: Span, env: &mut Environment) -> EvalResult {
+    if env.constants.contains(name) {
+        return Err(EvalError::ConstantAssignment(name.to_string()).at(span));
+    }
+    let x = value.eval(env)?;
+    env.set(name, x.clone());
+    Ok(x)
+}
+
+fn call(name: &str, args: &[Expr], span: Span, env: &mut Environment) -> EvalResult {
+    let values = args.iter()
+        .map(|a| a.eval(env))
+        .collect::<Result<Vec<Value>, _>>()?;
+    if let Some(f) = env.user_functions.get(name).cloned() {
+        return call_user(name, &f, values, span, env);
+    }
+    match env.functions.get(name) {
+        Some(f) => f.call(name, &values, &env.settings).map_err(|e| e.at(span)),
+        // `x(2)` with a variable `x` is an implicit product
+        None if env.settings.implicit_multiplication && values.len() == 1 => {
+            match env.get(name) {
+                Some(x) => x.and_then(|x| Value::binary(BinOp::Mul, x, values[0].clone(), &env.settings))
+                    .map_err(|e| e.at(span)),
+                None => Err(EvalError::UnknownFunction(name.to_string()).at(span)),
+            }
+        }
+        None => Err(EvalError::UnknownFunction(name.to_string()).at(span)),
+    }
+}
+
+impl Program {
+    /* evaluates every statement against `env` and returns the last value */
+    pub fn eval(&self, env: &mut Environment) -> EvalResult {
+        let mut last = Value::from_integer(0, &env.settings);
+        for statement in &self.statements {
+            last = statement.eval(env)?;
+        }
+        Ok(last)
+    }
+}
+
+fn call_user(name: &str, f: &UserFunction, values: Vec<Value>, span: Span, env: &mut Environment)
+        -> EvalResult {
+    if values.len() != f.params.len() {
+        return Err(EvalError::ArityMismatch {
+            function: name.to_string(),
+            expected: Arity::Exact(f.params.len()),
+            found: values.len(),
+        }.at(span));
+    }
+    if env.depth() >= MAX_CALL_DEPTH {
+        return Err(EvalError::RecursionLimit(MAX_CALL_DEPTH).at(span));
+    }
+    let frame: HashMap<String, Value> = f.params.iter().cloned().zip(values).collect();
+    env.push_frame(frame);
+    let res = f.body.eval(env);
+    env.pop_frame();
+    // the body's spans point into the source it was defined in, so report at the call site
+    res.map_err(|e| e.node.at(span))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::env::{Environment, MAX_CALL_DEPTH, MAX_EVAL_DEPTH};
+    use crate::parser::error::{Error, EvalError};
+    use crate::parser::settings::AngleUnit;
+    use crate::parser::value::Value;
+
+    fn eval(input: &str) -> Result<Value, Error> {
+        crate::eval(input, &mut Environment::new())
+    }
+
+    #[test]
+    fn parameters_shadow_globals() {
+        assert_eq!(eval("let x = 5; let f(x) = 2x; f(3) + x"), Ok(Value::Float(11.0)));
+    }
+
+    #[test]
+    fn comparisons_give_booleans() {
+        assert_eq!(eval("1 < 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval("1 == 1.0"), Ok(Value::Bool(true)));
+        assert_eq!(eval("2 != 2"), Ok(Value::Bool(false)));
+        assert_eq!(eval("3 >= 3 and 3 <= 2"), Ok(Value::Bool(false)));
+        assert_eq!(eval("true == (1 < 2)"), Ok(Value::Bool(true)));
+        assert_eq!(eval("i == i"), Ok(Value::Bool(true)));
+        assert!(eval("i < 1").is_err());
+        assert!(eval("true + 1").is_err());
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        // `x` is undefined, so these only succeed if it is never evaluated
+        assert_eq!(eval("1 < 2 or x"), Ok(Value::Bool(true)));
+        assert_eq!(eval("0 and x"), Ok(Value::Bool(false)));
+        assert!(eval("1 and x").is_err());
+        assert_eq!(eval("not 1 > 2"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn conditionals_evaluate_one_branch() {
+        assert_eq!(eval("if(1 > 0, 10, x)"), Ok(Value::Float(10.0)));
+        assert_eq!(eval("0 ? x : 5"), Ok(Value::Float(5.0)));
+        assert_eq!(eval("0 ? 2 : 0 ? 4 : 5"), Ok(Value::Float(5.0)));
+        assert_eq!(eval("let f(n) = n <= 1 ? 1 : n * f(n - 1); f(5)"), Ok(Value::Float(120.0)));
+    }
+
+    #[test]
+    fn percentages_and_remainders() {
+        assert_eq!(eval("10 % -3"), Ok(Value::Float(1.0)));
+        assert_eq!(eval("7 % +2"), Ok(Value::Float(1.0)));
+        assert_eq!(eval("50%"), Ok(Value::Float(0.5)));
+        assert_eq!(eval("200 + 10%"), Ok(Value::Float(220.0)));
+        assert_eq!(eval("200 - 10%"), Ok(Value::Float(180.0)));
+        assert_eq!(eval("200 * 10%"), Ok(Value::Float(20.0)));
+    }
+
+    #[test]
+    fn angle_suffixes_override_the_mode() {
+        let mut env = Environment::new();
+        env.settings.angle = AngleUnit::Degrees;
+        assert_eq!(crate::eval("sin(pi/6 rad)", &mut env), Ok(Value::Float(0.5)));
+        assert_eq!(crate::eval("sin(90 deg + pi/2 rad)", &mut env), Ok(Value::Float(0.0)));
+        env.settings.angle = AngleUnit::Radians;
+        assert_eq!(crate::eval("cos(2 * 30 deg)", &mut env), Ok(Value::Float(0.5000000000000001)));
+    }
+
+    #[test]
+    fn recursion_up_to_the_limit() {
+        let depth = MAX_CALL_DEPTH - 1;
+        let input = format!("let f(n) = n == 0 ? 0 : 1 + f(n-1); f({})", depth);
+        assert_eq!(eval(&input), Ok(Value::Float(depth as f64)));
+    }
+
+    #[test]
+    fn recursion_past_the_limit_is_an_error() {
+        let input = format!("let f(n) = n == 0 ? 0 : 1 + f(n-1); f({})", MAX_CALL_DEPTH);
+        match eval(&input) {
+            Err(Error::Eval(e)) => assert_eq!(e.node, EvalError::RecursionLimit(MAX_CALL_DEPTH)),
+            other => panic!("expected a recursion error, got {:?}", other),
+        }
+        assert!(eval("let g(n) = g(n + 1); g(0)").is_err());
+    }
+
+    #[test]
+    fn long_chains_are_not_nested() {
+        let input = format!("1{}", " + 1".repeat(10_000));
+        assert_eq!(eval(&input), Ok(Value::Float(10_001.0)));
+        let input = format!("2{}", " * 1 - 1 + 1".repeat(10_000));
+        assert_eq!(eval(&input), Ok(Value::Float(2.0)));
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_an_error() {
+        // each call nests its body, so the calls run out of depth before they reach `MAX_CALL_DEPTH`
+        let body = format!("{}f(n-1){}", "-(".repeat(20), ")".repeat(20));
+        let input = format!("let f(n) = n == 0 ? 0 : {}; f({})", body, MAX_CALL_DEPTH - 1);
+        match eval(&input) {
+            Err(Error::Eval(e)) => assert_eq!(e.node, EvalError::NestingLimit(MAX_EVAL_DEPTH)),
+            other => panic!("expected a nesting error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::Signed;
+
+use crate::parser::ast::BinOp;
+use crate::parser::error::{check_overflow, EvalError};
+use crate::parser::settings::{AngleUnit, NumberMode, Settings};
+use crate::parser::special;
+use crate::parser::units::{self, Quantity};
+use crate::parser::value::{self, Value};
+
+/* largest argument for which factorial is computed exactly in decimal mode */
+const MAX_EXACT_FACTORIAL: u64 = 5000;
+
+/* 300!! is the largest double factorial that fits in an f64 */
+const MAX_DOUBLE_FACTORIAL: f64 = 300.0;
+
+pub type FloatFn = fn(&[f64]) -> Result<f64, EvalError>;
+pub type ValueFn = fn(&[Value], &Settings) -> Result<Value, EvalError>;
+
+/* float functions see every argument as an f64 and have their result converted
+   back to the current number mode; value functions work on the numbers directly,
+   and only `any` functions also accept booleans and quantities with units */
+#[derive(Clone, Copy)]
+pub enum NativeFn {
+    Float(FloatFn),
+    Value(ValueFn),
+    Any(ValueFn),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(a) => n == a,
+            Arity::AtLeast(a) => n >= a,
+            Arity::Range(lo, hi) => n >= lo && n <= hi,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Exact(a) => write!(f, "{}", a),
+            Arity::AtLeast(a) => write!(f, "at least {}", a),
+            Arity::Range(lo, hi) => write!(f, "{} to {}", lo, hi),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Function {
+    pub arity: Arity,
+    pub func: NativeFn,
+}
+
+impl Function {
+    /* checks the argument count before running the native implementation */
+    pub fn call(&self, name: &str, args: &[Value], settings: &Settings) -> Result<Value, EvalError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(EvalError::ArityMismatch {
+                function: name.to_string(),
+                expected: self.arity,
+                found: args.len(),
+            });
+        }
+        match self.func {
+            NativeFn::Float(_) | NativeFn::Value(_) if args.iter().any(Value::is_bool) => {
+                Err(EvalError::BooleanArgument(name.to_string()))
+            }
+            NativeFn::Float(_) | NativeFn::Value(_) if args.iter().any(Value::is_quantity) => {
+                Err(EvalError::UnitArgument(name.to_string()))
+            }
+            NativeFn::Float(_) if args.iter().any(Value::is_complex) => {
+                Err(EvalError::ComplexArgument(name.to_string()))
+            }
+            NativeFn::Float(f) => {
+                let xs: Vec<f64> = args.iter().map(Value::to_f64).collect();
+                Value::from_f64(f(&xs)?, settings)
+            }
+            NativeFn::Value(f) | NativeFn::Any(f) => f(args, settings),
+        }
+    }
+}
+
+/* maps function names to native implementations; new built-ins only need a
+   `register` call, the parser accepts any name followed by an argument list */
+#[derive(Clone)]
+pub struct Registry {
+    functions: HashMap<String, Function>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { functions: HashMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, arity: Arity, func: FloatFn) {
+        self.functions.insert(name.to_string(), Function { arity, func: NativeFn::Float(func) });
+    }
+
+    pub fn register_value(&mut self, name: &str, arity: Arity, func: ValueFn) {
+        self.functions.insert(name.to_string(), Function { arity, func: NativeFn::Value(func) });
+    }
+
+    pub fn register_any(&mut self, name: &str, arity: Arity, func: ValueFn) {
+        self.functions.insert(name.to_string(), Function { arity, func: NativeFn::Any(func) });
+    }
+
+    pub fn alias(&mut self, alias: &str, name: &str) {
+        if let Some(f) = self.functions.get(name).copied() {
+            self.functions.insert(alias.to_string(), f);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(|k| &k[..]).collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut r = Registry::new();
+        r.register_value("sin", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, s| Ok(sin_cos("sin", x, s)?.0), Complex64::sin, |_| false)
+        });
+        r.register_value("cos", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, s| Ok(sin_cos("cos", x, s)?.1), Complex64::cos, |_| false)
+        });
+        r.register_value("tan", Arity::Exact(1), |a, s| complex_aware(a, s, tan, Complex64::tan, |_| false));
+        r.register_value("asin", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, s| inverse("asin", x.asin(), x, s), Complex64::asin, |x| x.abs() > 1.0)
+        });
+        r.register_value("acos", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, s| inverse("acos", x.acos(), x, s), Complex64::acos, |x| x.abs() > 1.0)
+        });
+        r.register_value("atan", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, s| inverse("atan", x.atan(), x, s), Complex64::atan, |_| false)
+        });
+        r.register_value("atan2", Arity::Exact(2), |a, s| {
+            if a.iter().any(Value::is_complex) {
+                return Err(EvalError::ComplexArgument("atan2".to_string()));
+            }
+            let (y, x) = (a[0].to_f64(), a[1].to_f64());
+            Value::from_f64(s.angle.from_radians(y.atan2(x)), s)
+        });
+        r.register_any("sqrt", Arity::Exact(1), |a, s| match &a[0] {
+            Value::Bool(_) => Err(EvalError::BooleanArgument("sqrt".to_string())),
+            q @ Value::Quantity(_) => units::quantity_binary(BinOp::Pow, q.clone(), Value::Float(0.5)),
+            Value::Decimal(d) => value::decimal_sqrt(d, s),
+            _ => complex_aware(a, s, |x, _| sqrt(x), Complex64::sqrt, |x| x < 0.0),
+        });
+        r.register_value("exp", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, _| check_overflow(x.exp(), &[x]), Complex64::exp, |_| false)
+        });
+        r.register_value("ln", Arity::Exact(1), |a, s| {
+            complex_aware(a, s, |x, _| log(x, std::f64::consts::E), Complex64::ln, |x| x < 0.0)
+        });
+        r.register("log", Arity::Range(1, 2), |a| log(a[0], a.get(1).copied().unwrap_or(10.0)));
+        r.register_any("abs", Arity::Exact(1), |a, s| Ok(match &a[0] {
+            Value::Float(x) => Value::Float(x.abs()),
+            Value::Decimal(d) => Value::Decimal(d.abs()),
+            Value::Rational(q) => Value::Rational(q.abs()),
+            Value::Complex(c) => Value::Float(c.norm()),
+            Value::Integer(n) => Value::Integer(n.wrapping_abs()).wrapped(s),
+            Value::Quantity(q) => Value::Quantity(Quantity { value: q.value.abs(), ..q.clone() }),
+            Value::Bool(_) => return Err(EvalError::BooleanArgument("abs".to_string())),
+        }));
+        r.register_value("re", Arity::Exact(1), |a, _| Ok(match &a[0] {
+            Value::Complex(c) => Value::Float(c.re),
+            v => v.clone(),
+        }));
+        r.register_value("im", Arity::Exact(1), |a, s| Ok(match &a[0] {
+            Value::Complex(c) => Value::Float(c.im),
+            _ => Value::from_integer(0, s),
+        }));
+        r.register_value("conj", Arity::Exact(1), |a, _| Ok(match &a[0] {
+            Value::Complex(c) => Value::Complex(c.conj()),
+            v => v.clone(),
+        }));
+        r.register_value("arg", Arity::Exact(1), |a, s| {
+            Value::from_f64(s.angle.from_radians(a[0].to_complex().arg()), s)
+        });
+        r.register_any("floor", Arity::Exact(1), |a, _| to_integer("floor", &a[0], RoundingMode::Floor));
+        r.register_any("ceil", Arity::Exact(1), |a, _| to_integer("ceil", &a[0], RoundingMode::Ceiling));
+        r.register_any("round", Arity::Exact(1), |a, _| to_integer("round", &a[0], RoundingMode::HalfUp));
+        r.register_value("max", Arity::AtLeast(1), |a, _| Ok(extreme(a, std::cmp::Ordering::Greater)));
+        r.register_value("min", Arity::AtLeast(1), |a, _| Ok(extreme(a, std::cmp::Ordering::Less)));
+        r.register_value("fact", Arity::Exact(1), |a, s| factorial(&a[0], s));
+        r.register_value("dfact", Arity::Exact(1), |a, s| double_factorial(&a[0], s));
+        r.register("gamma", Arity::Exact(1), gamma);
+        r.register("lgamma", Arity::Exact(1), lgamma);
+        r.register("beta", Arity::Exact(2), beta);
+        r.register_value("rol", Arity::Exact(2), |a, s| rotate(a, s, true));
+        r.register_value("ror", Arity::Exact(2), |a, s| rotate(a, s, false));
+        // convert(100, USD, EUR) is 100 USD to EUR
+        r.register_any("convert", Arity::Exact(3), |a, _| {
+            let unit = match &a[2] {
+                Value::Quantity(q) if q.value == 1.0 => q.unit.clone(),
+                _ => return Err(EvalError::ConversionTarget),
+            };
+            let q = Quantity::of(units::quantity_binary(BinOp::Mul, a[0].clone(), a[1].clone())?)?;
+            Ok(Value::Quantity(Quantity { value: q.convert(&unit)?, unit }))
+        });
+        r.register_any("print", Arity::Exact(1), |a, _| {
+            println!("{}", a[0]);
+            Ok(a[0].clone())
+        });
+        r.alias("sine", "sin");
+        r.alias("cosine", "cos");
+        r.alias("tangent", "tan");
+        r.alias("SQRT", "sqrt");
+        r
+    }
+}
+
+fn domain_error(function: &str, arg: f64) -> EvalError {
+    EvalError::DomainError { function: function.to_string(), arg }
+}
+
+fn finite_arg(function: &str, x: f64) -> Result<f64, EvalError> {
+    if x.is_infinite() {
+        return Err(domain_error(function, x));
+    }
+    Ok(x)
+}
+
+/* applies `real` to real arguments and `complex` to complex ones; in complex
+   mode real arguments for which `widen` holds take the complex path too.
+   Complex arguments and results of trigonometric functions are always in radians */
+fn complex_aware(
+    a: &[Value],
+    settings: &Settings,
+    real: fn(f64, &Settings) -> Result<f64, EvalError>,
+    complex: fn(Complex64) -> Complex64,
+    widen: fn(f64) -> bool,
+) -> Result<Value, EvalError> {
+    match &a[0] {
+        Value::Complex(c) => Ok(Value::Complex(complex(*c))),
+        v if settings.mode == NumberMode::Complex && widen(v.to_f64()) => {
+            Ok(Value::Complex(complex(v.to_complex())))
+        }
+        v => Value::from_f64(real(v.to_f64(), settings)?, settings),
+    }
+}
+
+/* sine and cosine of an angle in the current unit; outside radians the results
+   are rounded to 15 places so that sin(180) is 0 rather than 1.2e-16 */
+fn sin_cos(function: &str, x: f64, settings: &Settings) -> Result<(f64, f64), EvalError> {
+    let (sin, cos) = settings.angle.to_radians(finite_arg(function, x)?).sin_cos();
+    if settings.angle == AngleUnit::Radians {
+        return Ok((sin, cos));
+    }
+    let snap = |y: f64| (y * 1e15).round() / 1e15;
+    Ok((snap(sin), snap(cos)))
+}
+
+fn tan(x: f64, settings: &Settings) -> Result<f64, EvalError> {
+    if settings.angle == AngleUnit::Radians {
+        return Ok(finite_arg("tan", x)?.tan());
+    }
+    let (sin, cos) = sin_cos("tan", x, settings)?;
+    if cos == 0.0 {
+        return Err(domain_error("tan", x));
+    }
+    Ok(sin / cos)
+}
+
+/* converts the radians returned by an inverse function to the current unit,
+   rounding away the error of the conversion so that asin(0.5) is exactly 30 */
+fn inverse(function: &str, radians: f64, x: f64, settings: &Settings) -> Result<f64, EvalError> {
+    if radians.is_nan() && !x.is_nan() {
+        return Err(domain_error(function, x));
+    }
+    if settings.angle == AngleUnit::Radians {
+        return Ok(radians);
+    }
+    Ok((settings.angle.from_radians(radians) * 1e12).round() / 1e12)
+}
+
+fn sqrt(x: f64) -> Result<f64, EvalError> {
+    if x < 0.0 {
+        return Err(domain_error("sqrt", x));
+    }
+    Ok(x.sqrt())
+}
+
+fn log(x: f64, base: f64) -> Result<f64, EvalError> {
+    if x <= 0.0 {
+        return Err(domain_error("log", x));
+    }
+    if base <= 0.0 || base == 1.0 {
+        return Err(domain_error("log", base));
+    }
+    Ok(x.ln() / base.ln())
+}
+
+/* rounds to a whole number; quantities are rounded in their own unit */
+fn to_integer(name: &str, v: &Value, mode: RoundingMode) -> Result<Value, EvalError> {
+    let float = |x: f64| match mode {
+        RoundingMode::Floor => x.floor(),
+        RoundingMode::Ceiling => x.ceil(),
+        _ => x.round(),
+    };
+    Ok(match v {
+        Value::Float(x) => Value::Float(float(*x)),
+        Value::Decimal(d) => Value::Decimal(d.with_scale_round(0, mode)),
+        Value::Rational(q) => Value::Rational(match mode {
+            RoundingMode::Floor => q.floor(),
+            RoundingMode::Ceiling => q.ceil(),
+            _ => q.round(),
+        }),
+        Value::Complex(c) => Value::Complex(Complex64::new(float(c.re), float(c.im))),
+        Value::Integer(n) => Value::Integer(*n),
+        Value::Quantity(q) => Value::Quantity(Quantity { value: float(q.value), ..q.clone() }),
+        Value::Bool(_) => return Err(EvalError::BooleanArgument(name.to_string())),
+    })
+}
+
+/* the first argument that compares as `wanted` against all others */
+fn extreme(args: &[Value], wanted: std::cmp::Ordering) -> Value {
+    let mut best = &args[0];
+    for v in &args[1..] {
+        if v.compare(best) == Some(wanted) {
+            best = v;
+        }
+    }
+    best.clone()
+}
+
+/* rotates the bits of a[0] by a[1] places within the integer mode's word size */
+fn rotate(a: &[Value], settings: &Settings, left: bool) -> Result<Value, EvalError> {
+    let name = if left { "rol" } else { "ror" };
+    let ty = settings.integer;
+    let bits = ty.bits as i128;
+    let x = (ty.wrap(a[0].to_integral(name)?) as u128) & ty.mask();
+    let mut n = a[1].to_integral(name)?.rem_euclid(bits) as u32;
+    if !left {
+        n = (ty.bits - n) % ty.bits;
+    }
+    let rotated = if n == 0 { x } else { ((x << n) | (x >> (ty.bits - n))) & ty.mask() };
+    Ok(Value::from_integer(ty.wrap(rotated as i128), settings))
+}
+
+/* exact n(n-step)(n-2step)... of small non-negative integers in the decimal and
+   rational modes */
+fn exact_factorial(v: &Value, step: usize) -> Option<BigInt> {
+    let n = match v {
+        Value::Decimal(d) if d.is_integer() => d.to_u64()?,
+        Value::Rational(q) if q.is_integer() => q.to_integer().to_u64()?,
+        _ => return None,
+    };
+    if n > MAX_EXACT_FACTORIAL {
+        return None;
+    }
+    Some((1..=n).rev().step_by(step).fold(BigInt::from(1), |acc, k| acc * k))
+}
+
+fn exact_value(product: BigInt, like: &Value, settings: &Settings) -> Value {
+    match like {
+        Value::Rational(_) => Value::Rational(BigRational::from_integer(product)),
+        _ => Value::Decimal(value::round(BigDecimal::from(product), settings.precision)),
+    }
+}
+
+/* x!, defined as gamma(x + 1) for non-integers */
+pub fn factorial(v: &Value, settings: &Settings) -> Result<Value, EvalError> {
+    if v.is_complex() {
+        return Err(EvalError::ComplexArgument("fact".to_string()));
+    }
+    if let Some(product) = exact_factorial(v, 1) {
+        return Ok(exact_value(product, v, settings));
+    }
+    let x = v.to_f64();
+    if special::is_pole(x + 1.0) {
+        return Err(domain_error("fact", x));
+    }
+    Value::from_f64(check_overflow(special::gamma(x + 1.0), &[x])?, settings)
+}
+
+/* x!! = x(x-2)(x-4)..., for integers from -1 up */
+pub fn double_factorial(v: &Value, settings: &Settings) -> Result<Value, EvalError> {
+    if v.is_complex() {
+        return Err(EvalError::ComplexArgument("dfact".to_string()));
+    }
+    if let Some(product) = exact_factorial(v, 2) {
+        return Ok(exact_value(product, v, settings));
+    }
+    let x = v.to_f64();
+    if x.fract() != 0.0 || x < -1.0 {
+        return Err(domain_error("dfact", x));
+    }
+    if x > MAX_DOUBLE_FACTORIAL {
+        return Err(EvalError::Overflow);
+    }
+    let product = (1..=x.max(0.0) as u64).rev().step_by(2).fold(1.0, |acc, k| acc * k as f64);
+    Value::from_f64(check_overflow(product, &[x])?, settings)
+}
+
+fn gamma(a: &[f64]) -> Result<f64, EvalError> {
+    if special::is_pole(a[0]) {
+        return Err(domain_error("gamma", a[0]));
+    }
+    check_overflow(special::gamma(a[0]), a)
+}
+
+fn lgamma(a: &[f64]) -> Result<f64, EvalError> {
+    if special::is_pole(a[0]) {
+        return Err(domain_error("lgamma", a[0]));
+    }
+    check_overflow(special::lgamma(a[0]), a)
+}
+
+fn beta(a: &[f64]) -> Result<f64, EvalError> {
+    if let Some(&x) = a.iter().find(|&&x| special::is_pole(x)) {
+        return Err(domain_error("beta", x));
+    }
+    check_overflow(special::beta(a[0], a[1]), a)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::env::Environment;
+    use crate::parser::error::{Error, EvalError};
+    use crate::parser::settings::{NumberMode, Settings};
+    use crate::parser::value::Value;
+
+    fn eval_in(mode: NumberMode, input: &str) -> Result<Value, EvalError> {
+        let mut env = Environment::new();
+        env.settings.mode = mode;
+        crate::eval(input, &mut env).map_err(|e| match e {
+            Error::Eval(e) => e.node,
+            e => panic!("{} does not parse: {:?}", input, e),
+        })
+    }
+
+    fn undefined(function: &str, arg: f64) -> EvalError {
+        EvalError::DomainError { function: function.to_string(), arg }
+    }
+
+    #[test]
+    fn factorial_follows_gamma() {
+        assert_eq!(eval_in(NumberMode::Float, "5!"), Ok(Value::Float(120.0)));
+        assert_eq!(eval_in(NumberMode::Float, "fact(0.5)"), Ok(Value::Float(0.886_226_925_452_758_6)));
+        assert_eq!(eval_in(NumberMode::Float, "fact(-0.5)"), Ok(Value::Float(1.772_453_850_905_515_9)));
+        assert_eq!(eval_in(NumberMode::Float, "(-2)!"), Err(undefined("fact", -2.0)));
+        assert_eq!(eval_in(NumberMode::Float, "171!"), Err(EvalError::Overflow));
+        assert_eq!(eval_in(NumberMode::Float, "9!!"), Ok(Value::Float(945.0)));
+    }
+
+    #[test]
+    fn exact_modes_multiply_out_large_factorials() {
+        let settings = Settings { mode: NumberMode::Decimal, ..Settings::default() };
+        let decimal = eval_in(NumberMode::Decimal, "171!").unwrap().format(&settings);
+        assert_eq!(decimal, "1.2410180702176678234248405241031039926166055775017e309");
+        let rational = eval_in(NumberMode::Rational, "171!").unwrap().to_string();
+        assert!(rational.starts_with("124101807021766782342484052410310399") && rational.len() == 310);
+        for mode in [NumberMode::Decimal, NumberMode::Rational] {
+            assert_eq!(eval_in(mode, "5001!"), Err(EvalError::Overflow), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn gamma_builtins() {
+        assert_eq!(eval_in(NumberMode::Float, "gamma(0)"), Err(undefined("gamma", 0.0)));
+        assert_eq!(eval_in(NumberMode::Float, "beta(2, 3)"), Ok(Value::Float(1.0 / 12.0)));
+        assert!(eval_in(NumberMode::Float, "lgamma(1000)").is_ok());
+    }
+}
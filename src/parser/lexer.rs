@@ -1,4 +1,9 @@
 use std::fmt;
+
+use num_bigint::BigInt;
+
+use crate::parser::error::ParseError;
+use crate::parser::span::{Span, Spanned};
 use crate::parser::token;
 use crate::parser::token::Token::*;
 
@@ -6,84 +11,218 @@ pub struct Lexer {
     pub curr:  char,
     pub pos: usize,
     pub src: String,
+    pub chars: Vec<char>,
     pub eof: bool
 }
 
 impl Lexer {
     pub fn new(src: &str) -> Lexer {
+        let chars: Vec<char> = src.chars().collect();
         let mut l = Lexer {
             curr: '\0',
             pos: 0,
             src: src.to_string(),
+            chars,
             eof: false
         };
-        if l.pos >= src.len() {
+        if l.pos >= l.chars.len() {
             l.eof = true;
         } else {
-            l.curr = src.chars().nth(0).unwrap();
+            l.curr = l.chars[0];
         }
         l
     }
-    pub fn next_token(&mut self) -> Result<token::Token, String> {
+    pub fn next_token(&mut self) -> Result<Spanned<token::Token>, ParseError> {
+        self.consume_whitespace();
+        let start = self.pos;
         if self.eof {
-            return Ok(EOF);
+            return Ok(Spanned::new(EOF, Span::new(start, start)));
         }
-        self.consume_whitespace();
-        match self.curr {
-            
-            '(' => {self.bump(); Ok(LPAREN)}
-            ')' => {self.bump(); Ok(RPAREN)}
-            c if c.is_digit(10) => {
-                let start = self.pos;
-                let mut end = start + 1;
-                self.bump();
-                while (self.curr.is_digit(10) || self.curr == '.') && !self.eof{
+        let tok = match self.curr {
+
+            '(' => {self.bump(); LPAREN}
+            ')' => {self.bump(); RPAREN}
+            ',' => {self.bump(); COMMA}
+            ';' => {self.bump(); SEMICOLON}
+            '\n' => {self.bump(); NEWLINE}
+            c if c.is_ascii_digit() => {
+                let text = self.number(start)?;
+                // `2i` is an imaginary literal, but `2in` is not
+                if !self.eof && self.curr == 'i' && !self.peek_char().is_some_and(is_symbol_char) {
                     self.bump();
-                    end += 1;
+                    return Ok(Spanned::new(NUMBER(format!("{}i", text)), Span::new(start, self.pos)));
                 }
-                Ok(NUMBER(self.src[start..end].parse::<f64>().unwrap()))
+                NUMBER(text)
             }
-       
+
             c if c.is_alphabetic() => {
-                let start = self.pos;
-                let mut end = start + 1;
                 self.bump();
-                while self.curr.is_alphabetic() && !self.eof {
+                while is_symbol_char(self.curr) && !self.eof {
                     self.bump();
-                    end += 1;
                 }
-                Ok(SYMBOL(self.src[start..end].to_string()))
+                SYMBOL(self.slice(start))
             }
-            '+' => {self.bump(); Ok(ADD)}
-            '-' => {self.bump(); Ok(SUB)}
-            '*' => {self.bump(); Ok(MUL)}
-            '/' => {self.bump(); Ok(DIV)}
-            '^' => {self.bump(); Ok(CARET)}
-            '=' => {self.bump(); Ok(EQUALS)}
-            '%' => {self.bump(); Ok(MOD)}
-            c => { Err(format!("unexpected token {} at position {}", c, self.pos)) }
-        }
+            '+' => {self.bump(); ADD}
+            '-' => {self.bump(); SUB}
+            '*' => {self.bump(); MUL}
+            '/' => {self.bump(); DIV}
+            '^' => {self.bump(); CARET}
+            '=' => {
+                self.bump();
+                if !self.eof && self.curr == '=' {
+                    self.bump();
+                    EQ
+                } else {
+                    EQUALS
+                }
+            }
+            '&' => {self.bump(); AMP}
+            '|' => {self.bump(); PIPE}
+            '~' => {self.bump(); TILDE}
+            '<' | '>' => {
+                let c = self.curr;
+                self.bump();
+                match (c, self.eof, self.curr) {
+                    ('<', false, '<') => {self.bump(); SHL}
+                    ('>', false, '>') => {self.bump(); SHR}
+                    ('<', false, '=') => {self.bump(); LE}
+                    ('>', false, '=') => {self.bump(); GE}
+                    ('<', _, _) => LT,
+                    _ => GT,
+                }
+            }
+            '?' => {self.bump(); QUESTION}
+            ':' => {self.bump(); COLON}
+            // modulo or percent; the parser tells them apart by what follows
+            '%' => {self.bump(); MOD}
+            '!' => {
+                self.bump();
+                match (self.eof, self.curr) {
+                    (false, '!') => {self.bump(); DBANG}
+                    (false, '=') => {self.bump(); NE}
+                    _ => BANG,
+                }
+            }
+            c => {
+                return Err(ParseError::new(
+                    format!("unexpected character '{}'", c),
+                    Span::new(start, start + 1)));
+            }
+        };
+        Ok(Spanned::new(tok, Span::new(start, self.pos)))
     }
     pub fn bump(&mut self) {
         self.pos += 1;
-        if self.pos >= self.src.len() {
+        if self.pos >= self.chars.len() {
             self.eof = true;
             return;
         }
-        self.curr = self.src.chars().nth(self.pos).unwrap();
+        self.curr = self.chars[self.pos];
     }
 
     pub fn consume_whitespace(&mut self) {
-        while is_whitespace(self.curr) {
+        while !self.eof && is_whitespace(self.curr) {
+            self.bump();
+        }
+    }
+
+    /* reads a numeric literal into plain decimal text, so `1_000` becomes `1000`
+       and `0xFF` becomes `255`; exponents are kept as in `1.5e-3` */
+    fn number(&mut self, start: usize) -> Result<String, ParseError> {
+        let radix = match (self.curr, self.peek_char()) {
+            ('0', Some('x' | 'X')) => Some(16),
+            ('0', Some('o' | 'O')) => Some(8),
+            ('0', Some('b' | 'B')) => Some(2),
+            _ => None,
+        };
+        // without digits, `0x` is zero times x
+        if let Some(radix) = radix.filter(|_| self.char_at(2).is_some_and(is_symbol_char)) {
+            self.bump();
+            self.bump();
+            let digits_start = self.pos;
+            while !self.eof && is_symbol_char(self.curr) {
+                self.bump();
+            }
+            return without_separators(&self.slice(digits_start), radix)
+                .and_then(|digits| BigInt::parse_bytes(digits.as_bytes(), radix))
+                .map(|n| n.to_string())
+                .ok_or_else(|| self.malformed(start));
+        }
+
+        self.digits();
+        if !self.eof && self.curr == '.' {
+            self.bump();
+            self.digits();
+        }
+        // an exponent needs digits, so `2e` is still 2 times e
+        if !self.eof && matches!(self.curr, 'e' | 'E') {
+            let sign = usize::from(matches!(self.peek_char(), Some('+' | '-')));
+            if self.char_at(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                for _ in 0..=sign {
+                    self.bump();
+                }
+                self.digits();
+            }
+        }
+        if !self.eof && self.curr == '.' {
+            // `1.2.3`: take the rest of it so the error covers the whole literal
+            while !self.eof && (self.curr == '.' || self.curr == '_' || self.curr.is_ascii_digit()) {
+                self.bump();
+            }
+            return Err(self.malformed(start));
+        }
+        without_separators(&self.slice(start), 10)
+            .filter(|text| text.parse::<f64>().is_ok())
+            .ok_or_else(|| self.malformed(start))
+    }
+
+    /* digits and `_` separators */
+    fn digits(&mut self) {
+        while !self.eof && (self.curr.is_ascii_digit() || self.curr == '_') {
             self.bump();
         }
     }
+
+    fn malformed(&self, start: usize) -> ParseError {
+        ParseError::new(
+            format!("malformed number '{}'", self.slice(start)),
+            Span::new(start, self.pos))
+    }
+
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.char_at(1)
+    }
+
+    fn slice(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
 }
 pub fn is_whitespace(c: char) -> bool {
-    match c {
-        ' ' | '\n' | '\t' => true,
-        _ => false
+    matches!(c, ' ' | '\t' | '\r')
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/* strips `_` digit separators, which must sit between two digits of `radix`,
+   so the `e` of an exponent is not one unless the literal is hexadecimal */
+fn without_separators(text: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            let before = i.checked_sub(1).map(|j| chars[j]);
+            let after = chars.get(i + 1).copied();
+            if !before.is_some_and(|c| c.is_digit(radix)) || !after.is_some_and(|c| c.is_digit(radix)) {
+                return None;
+            }
+        }
     }
+    Some(text.replace('_', ""))
 }
 
 
@@ -92,3 +231,71 @@ impl fmt::Display for Lexer {
         write!(f, "{}", self.src)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* every token up to the end of the input */
+    fn lex(input: &str) -> Result<Vec<token::Token>, ParseError> {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = Vec::new();
+        loop {
+            match lexer.next_token()?.node {
+                EOF => return Ok(tokens),
+                t => tokens.push(t),
+            }
+        }
+    }
+
+    fn number(input: &str) -> String {
+        match lex(input).unwrap().as_slice() {
+            [NUMBER(text)] => text.clone(),
+            tokens => panic!("{} lexed as {:?}", input, tokens),
+        }
+    }
+
+    #[test]
+    fn scientific_notation() {
+        assert_eq!(number("1.5e-3"), "1.5e-3");
+        assert_eq!(number("6.02E23"), "6.02E23");
+        assert_eq!(number("2e+5"), "2e+5");
+        // without digits after it the `e` is Euler's number
+        assert_eq!(lex("2e").unwrap(), vec![NUMBER("2".to_string()), SYMBOL("e".to_string())]);
+        assert_eq!(lex("2e-x").unwrap()[..2], [NUMBER("2".to_string()), SYMBOL("e".to_string())]);
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(number("0xFF"), "255");
+        assert_eq!(number("0Xff"), "255");
+        assert_eq!(number("0b1011"), "11");
+        assert_eq!(number("0o755"), "493");
+        assert_eq!(number("0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FF"), "87112285931760246646623899502532662132735");
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(number("1_000_000"), "1000000");
+        assert_eq!(number("0b1111_0000"), "240");
+        assert_eq!(number("1_000.000_1"), "1000.0001");
+        assert_eq!(number("1_5e3"), "15e3");
+        assert_eq!(number("0x1_e"), "30");
+    }
+
+    #[test]
+    fn malformed_literals_are_errors() {
+        for input in ["1.2.3", "0b102", "0o8", "1__0", "1_", "0xG", "1_e5", "1.5_e3", "0b1_2"] {
+            let e = lex(input).unwrap_err();
+            assert!(e.to_string().contains("malformed number"), "{}: {}", input, e);
+        }
+        assert_eq!(lex("1.2.3").unwrap_err().span, Span::new(0, 5));
+    }
+
+    #[test]
+    fn percent_is_always_one_token() {
+        // modulo and percent are told apart by the parser
+        assert_eq!(lex("50%").unwrap(), vec![NUMBER("50".to_string()), MOD]);
+        assert_eq!(lex("10 % -3").unwrap(), vec![NUMBER("10".to_string()), MOD, SUB, NUMBER("3".to_string())]);
+    }
+}
@@ -1,51 +1,151 @@
 pub use self::token::Token::*;
 
+use std::collections::HashSet;
+
+use self::ast::{BinOp, Expr, LogicalOp, Program, UnaryOp};
+use self::error::ParseError;
+use self::settings::AngleUnit;
+use self::span::{Span, Spanned};
+use self::units::Units;
+
 pub mod ast;
+pub mod constants;
+pub mod diagnostic;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod functions;
 pub mod lexer;
+pub mod settings;
+pub mod span;
+pub mod special;
 pub mod token;
+pub mod units;
+pub mod value;
+
 
+/* deepest nesting of parentheses, arguments and prefix or right-associative
+   operators that parsing follows; this fits the 2 MiB stack of a spawned
+   thread even in debug builds */
+pub const MAX_PARSE_DEPTH: usize = 100;
 
 pub struct Parser {
-    pub current: token::Token,
+    pub current: Spanned<token::Token>,
     pub lexer: lexer::Lexer,
-    pub peeked: Option<token::Token>,
+    pub peeked: Option<Spanned<token::Token>>,
+    /* the token after `peeked`, read only to tell modulo from a percentage */
+    after: Option<Spanned<token::Token>>,
+    /* read `2x`, `3(4+5)` and `2sin(x)` as products */
+    pub implicit_multiplication: bool,
+    /* names that bind tightly to a number before them, so 10 m / 2 s is 5 m/s */
+    pub units: HashSet<String>,
+    /* open parentheses; inside them a newline continues the expression */
+    depth: usize,
+    /* subexpressions being parsed, so deep input fails before the stack does */
+    nesting: usize,
 }
 
 impl Parser {
     pub fn new(input: &str) -> Parser {
         let l = lexer::Lexer::new(input);
-        let p = Parser {
-            current: EOF,
+        Parser {
+            current: Spanned::new(EOF, Span::default()),
             peeked: None,
-            lexer: l
-        };
-        p
+            after: None,
+            lexer: l,
+            implicit_multiplication: true,
+            units: Units::default().iter().map(|u| u.name.clone()).collect(),
+            depth: 0,
+            nesting: 0,
+        }
+    }
+
+    /* a parser that knows the units of `units` rather than only the built-in ones */
+    pub fn with_units(mut self, units: &Units) -> Parser {
+        self.units = units.iter().map(|u| u.name.clone()).collect();
+        self
+    }
+
+    /* a parser that requires every product to be written with `*` */
+    pub fn strict(mut self) -> Parser {
+        self.implicit_multiplication = false;
+        self
+    }
+
+    /* parses a single expression that must span the whole input */
+    pub fn parse(&mut self) -> Result<Expr, ParseError> {
+        let e = self.expr(1)?;
+        self.expect_end(token::is_eof)?;
+        Ok(e)
+    }
+
+    /* parses `;` or newline separated statements */
+    pub fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut statements = Vec::new();
+        loop {
+            while token::is_separator(&self.peek_token()?) {
+                self.next_token()?;
+            }
+            if token::is_eof(&self.peek_token()?) {
+                break;
+            }
+            statements.push(self.expr(1)?);
+            self.expect_end(|t| token::is_eof(t) || token::is_separator(t))?;
+        }
+        Ok(Program { statements })
     }
 
-    pub fn parse(&mut self) -> Result<Box<dyn ast::Node>, String> {
-        self.expr(1)
+    pub fn expr(&mut self, prec: usize) -> Result<Expr, ParseError> {
+        if self.nesting >= MAX_PARSE_DEPTH {
+            let span = self.peek()?.span;
+            return Err(ParseError::new("expression is nested too deeply".to_string(), span));
+        }
+        self.nesting += 1;
+        let e = self.operators(prec);
+        self.nesting -= 1;
+        e
     }
 
-    pub fn expr(&mut self, prec: usize) -> Result<Box<dyn ast::Node>, String> {
+    /* an operand followed by the operators binding at least as tightly as `prec` */
+    fn operators(&mut self, prec: usize) -> Result<Expr, ParseError> {
         let mut lhs = self.atom()?;
         let mut rhs;
-        
+
         loop {
             let curr = self.peek_token()?;
             if token::is_eof(&curr) {
-                //println!("breaking out of expr loop");
                 break;
             }
-            // if lhs.as_ref().eval(env)
-            let info_tuple = curr.info();
-            if info_tuple.is_none() {
-                break;
+            if let SYMBOL(name) = &curr {
+                if let Some(unit) = AngleUnit::from_suffix(name) {
+                    if token::SUFFIX_PREC < prec {
+                        break;
+                    }
+                    self.next_token()?;
+                    let span = lhs.span().to(self.current.span);
+                    lhs = Expr::Angle { arg: Box::new(lhs), unit, span };
+                    continue;
+                }
             }
-            let (op_prec, op_assoc) = info_tuple.unwrap();
+            // an operand right after another is an implicit `*` of the same
+            // precedence, so 2x^2 is 2*(x^2) and 1/2x is (1/2)*x
+            let (op, (op_prec, op_assoc), implicit) = match curr.info() {
+                Some(info) => (curr, info, false),
+                None if self.implicit_multiplication && token::starts_operand(&curr) => {
+                    (MUL, MUL.info().unwrap(), true)
+                }
+                None => break,
+            };
             if op_prec < prec {
                 break;
             }
-            self.next_token()?;
+            if !implicit {
+                self.next_token()?;
+            }
+            if op == QUESTION {
+                lhs = self.conditional(lhs, op_prec)?;
+                continue;
+            }
             match op_assoc {
                 0 => {
                     rhs = self.expr(op_prec + 1)?;
@@ -54,184 +154,517 @@ impl Parser {
                     rhs = self.expr(op_prec)?;
                 }
             }
-            lhs = self.op(curr, lhs, rhs);
+            lhs = self.op(op, lhs, rhs);
 
         }
         Ok(lhs)
     }
 
-    pub fn atom(&mut self) -> Result<Box<dyn ast::Node>, String> {
+    /* the `then : otherwise` of `cond ? then : otherwise`, after the `?` */
+    fn conditional(&mut self, cond: Expr, prec: usize) -> Result<Expr, ParseError> {
+        let then = self.expr(1)?;
+        self.expect(COLON)?;
+        let otherwise = self.expr(prec)?;
+        let span = cond.span().to(otherwise.span());
+        Ok(Expr::If { cond: Box::new(cond), then: Box::new(then), otherwise: Box::new(otherwise), span })
+    }
+
+    /* a primary expression with any postfix operators applied; these bind
+       tighter than `^` and a leading minus, so -3! is -(3!). A `%` with an
+       operand after it, even a signed one as in 10 % -3, is left for `expr` as
+       the remainder; otherwise it is a percentage */
+    pub fn atom(&mut self) -> Result<Expr, ParseError> {
+        let mut e = self.primary()?;
+        if let Expr::Num { .. } = e {
+            e = self.unit_suffix(e)?;
+        }
+        loop {
+            let op = match self.peek_token()? {
+                BANG => UnaryOp::Factorial,
+                DBANG => UnaryOp::DoubleFactorial,
+                MOD if !self.operand_after()? => UnaryOp::Percent,
+                _ => break,
+            };
+            self.next_token()?;
+            let span = e.span().to(self.current.span);
+            e = Expr::Unary { op, arg: Box::new(e), span };
+        }
+        Ok(e)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek_span()?;
         match self.peek_token()? {
-            EOF => { Ok(Box::new( ast::Num {num: 0f64})) }
+            EOF => Err(ParseError::new("expected an operand".to_string(), start)),
             LPAREN => {
-                self.expect('(')?;
+                self.open(LPAREN)?;
                 let e = self.expr(1)?;
-                self.expect(')')?;
+                self.close(RPAREN)?;
                 Ok(e)
             }
             NUMBER(val) => {
                 self.next_token()?;
-                Ok(Box::new( ast::Num { num: val }))
+                Ok(Expr::Num { literal: val, span: start })
+            }
+            SYMBOL(val) if val == "not" => {
+                self.next_token()?;
+                self.prefix(UnaryOp::Not, token::NOT_PREC, start)
             }
             SYMBOL(val) => {
-                //only allow math functions for now, no variables
                 self.next_token()?;
-                match self.peek_token()? {       
-                    LPAREN => {
-                        self.expect('(')?;
-                        let e = self.expr(1)?;
-                        self.expect(')')?;
-                        Ok(self.function(val,e))
-                    }
-                    SYMBOL(name) => {
-                        match &val[..] {
-                            "let" => {
-                                self.next_token()?;
-                                self.expect('=')?;
-                                let expr = self.expr(1)?;
-                                Ok(Box::new( ast::Assignment { name: name, value: expr}))
-                            }
-                            _ => {
-                                Err("Error: two consecutive symbols".to_string())
-                            }
-                        }
-                   }
-                   _ => {
-                       Ok(Box::new( ast::Var { name: val }))
-                   }
-                }
+                self.symbol(val, start)
             }
-            a => {
-                match a {
-                    SUB => {
-                        self.next_token()?;
-                        match self.peek_token()? {
-                            NUMBER(val) => {
-                                self.next_token()?;
-                                Ok(Box::new( ast::Num { num: -val }))
-                            }
-                            _ => Err(format!("unrecognized atom: {:?}", a))
-                        }
-                    },
-                    _ => Err(format!("unrecognized atom: {:?}", a))
+            SUB | ADD | TILDE => {
+                self.next_token()?;
+                let op = match self.current.node {
+                    SUB => UnaryOp::Neg,
+                    ADD => UnaryOp::Plus,
+                    _ => UnaryOp::BitNot,
+                };
+                if token::is_eof(&self.peek_token()?) {
+                    return Err(ParseError::new(
+                        "expected an operand after prefix operator".to_string(), start));
                 }
+                self.prefix(op, token::PREFIX_PREC, start)
             }
+            a => Err(ParseError::new(format!("expected an operand but found {}", a.describe()), start))
         }
     }
 
-    pub fn op (&self, op: token::Token, lhs: Box<dyn ast::Node>, rhs: Box<dyn ast::Node>)
-            -> Box<dyn ast::Node> {
-        match op {
-            ADD => {
-                Box::new( ast::Add {
-                    left: lhs,
-                    right: rhs
-                })
+    /* the operand of a prefix operator, which takes operators binding at least as tightly as `prec` */
+    fn prefix(&mut self, op: UnaryOp, prec: usize, start: Span) -> Result<Expr, ParseError> {
+        let arg = self.expr(prec)?;
+        let span = start.to(arg.span());
+        Ok(Expr::Unary { op, arg: Box::new(arg), span })
+    }
+
+    /* a call, definition, assignment or variable starting with the name `val` */
+    fn symbol(&mut self, val: String, start: Span) -> Result<Expr, ParseError> {
+        match self.peek_token()? {
+            LPAREN if val == "if" => {
+                let mut args = self.arguments()?;
+                let span = start.to(self.current.span);
+                if args.len() != 3 {
+                    return Err(ParseError::new(
+                        format!("if expects 3 arguments (condition, then, otherwise) but got {}", args.len()),
+                        span));
+                }
+                let otherwise = Box::new(args.pop().unwrap());
+                let then = Box::new(args.pop().unwrap());
+                let cond = Box::new(args.pop().unwrap());
+                Ok(Expr::If { cond, then, otherwise, span })
             }
-            SUB => {
-                Box::new( ast::Sub {
-                    left: lhs,
-                    right: rhs
-                })
+            LPAREN => {
+                let args = self.arguments()?;
+                let span = start.to(self.current.span);
+                Ok(Expr::Call { name: val, args, span })
             }
-            MUL => {
-                Box::new( ast::Mul {
-                    left: lhs,
-                    right: rhs
-                })
+            SYMBOL(name) => {
+                match &val[..] {
+                    "let" => {
+                        self.next_token()?;
+                        if self.peek_token()? == LPAREN {
+                            let params = self.parameters()?;
+                            self.expect(EQUALS)?;
+                            let body = self.expr(1)?;
+                            let span = start.to(body.span());
+                            return Ok(Expr::FnDef { name, params, body: Box::new(body), span });
+                        }
+                        self.expect(EQUALS)?;
+                        let expr = self.expr(1)?;
+                        let span = start.to(expr.span());
+                        Ok(Expr::Assign { name, value: Box::new(expr), span })
+                    }
+                    _ if AngleUnit::from_suffix(&name).is_some()
+                        || SYMBOL(name.clone()).info().is_some()
+                        || self.implicit_multiplication => {
+                        Ok(Expr::Var { name: val, span: start })
+                    }
+                    _ => {
+                        Err(ParseError::new(
+                            "two consecutive symbols".to_string(),
+                            start.to(self.peek_span()?)))
+                    }
+                }
             }
-            DIV => {
-                Box::new( ast::Div {
-                    left: lhs,
-                    right: rhs
-                })
+            _ => {
+                Ok(Expr::Var { name: val, span: start })
             }
-            MOD => {
-                Box::new( ast::Mod {
-                    left: lhs,
-                    right: rhs
-                })
+        }
+    }
+
+    /* whether the token after the peeked one can begin an operand */
+    fn operand_after(&mut self) -> Result<bool, ParseError> {
+        Ok(match self.peek_after()? {
+            NUMBER(_) | LPAREN | ADD | SUB | TILDE => true,
+            SYMBOL(name) => SYMBOL(name.clone()).info().is_none() && AngleUnit::from_suffix(&name).is_none(),
+            _ => false,
+        })
+    }
+
+    /* `5 km`, `3 ft^2` or `60 km/h`: units right after a number multiply it
+       before any other operator applies, and are units even where a variable
+       or constant has the same name */
+    fn unit_suffix(&mut self, number: Expr) -> Result<Expr, ParseError> {
+        if !self.unit_next()? {
+            return Ok(number);
+        }
+        let mut unit = self.unit_power()?;
+        // `/` followed by another unit name continues the unit
+        while self.peek_token()? == DIV && matches!(self.peek_after()?, SYMBOL(name) if self.units.contains(&name)) {
+            self.next_token()?;
+            let rhs = self.unit_power()?;
+            let span = unit.span().to(rhs.span());
+            unit = Expr::Binary { op: BinOp::Div, lhs: Box::new(unit), rhs: Box::new(rhs), span };
+        }
+        let span = number.span().to(unit.span());
+        Ok(Expr::Binary { op: BinOp::Mul, lhs: Box::new(number), rhs: Box::new(unit), span })
+    }
+
+    /* whether a unit name comes next; one followed by `(` is a call instead, so
+       `2 min(3, 4)` is 2 times the smaller of 3 and 4 */
+    fn unit_next(&mut self) -> Result<bool, ParseError> {
+        Ok(matches!(self.peek_token()?, SYMBOL(name) if self.units.contains(&name))
+            && self.peek_after()? != LPAREN)
+    }
+
+    /* a unit name with an optional power, as in `ft^2` */
+    fn unit_power(&mut self) -> Result<Expr, ParseError> {
+        self.next_token()?;
+        let name = match &self.current.node {
+            SYMBOL(name) => name.clone(),
+            t => unreachable!("unit_power called on {:?}", t),
+        };
+        let mut unit = Expr::Unit { name, span: self.current.span };
+        if self.peek_token()? == CARET {
+            self.next_token()?;
+            let power = self.expr(CARET.info().unwrap().0)?;
+            let span = unit.span().to(power.span());
+            unit = Expr::Binary { op: BinOp::Pow, lhs: Box::new(unit), rhs: Box::new(power), span };
+        }
+        Ok(unit)
+    }
+
+    /* the target of `to` names units, so its known unit names are read as such */
+    fn unit_names(&self, e: Expr) -> Expr {
+        match e {
+            Expr::Var { name, span } if self.units.contains(&name) => Expr::Unit { name, span },
+            Expr::Binary { op, lhs, rhs, span } => Expr::Binary {
+                op,
+                lhs: Box::new(self.unit_names(*lhs)),
+                rhs: Box::new(self.unit_names(*rhs)),
+                span,
+            },
+            e => e,
+        }
+    }
+
+    pub fn op (&self, op: token::Token, lhs: Expr, rhs: Expr) -> Expr {
+        let span = lhs.span().to(rhs.span());
+        let op = match op {
+            ADD => BinOp::Add,
+            SUB => BinOp::Sub,
+            MUL => BinOp::Mul,
+            DIV => BinOp::Div,
+            MOD => BinOp::Mod,
+            CARET => BinOp::Pow,
+            AMP => BinOp::BitAnd,
+            PIPE => BinOp::BitOr,
+            SYMBOL(ref s) if s == "xor" => BinOp::BitXor,
+            SHL => BinOp::Shl,
+            SHR => BinOp::Shr,
+            EQ => BinOp::Eq,
+            NE => BinOp::Ne,
+            LT => BinOp::Lt,
+            LE => BinOp::Le,
+            GT => BinOp::Gt,
+            GE => BinOp::Ge,
+            SYMBOL(ref s) if s == "to" || s == "in" => {
+                let target = Box::new(self.unit_names(rhs));
+                return Expr::Convert { arg: Box::new(lhs), target, span };
             }
-            CARET => {
-                Box::new( ast::Pow {
-                    base: lhs,
-                    exponent: rhs
-                })
+            SYMBOL(ref s) if s == "and" || s == "or" => {
+                let op = if s == "and" { LogicalOp::And } else { LogicalOp::Or };
+                return Expr::Logical { op, lhs: Box::new(lhs), rhs: Box::new(rhs), span };
             }
             o => {
                 panic!("unrecognized op: {:?}", o);
             }
-        }
+        };
+        Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs), span }
     }
 
-    pub fn function<'a>(&'a self, op: String, arg: Box<dyn ast::Node>) -> Box<dyn ast::Node> {
-        match &op[..] {
-            "sin" | "sine" => {
-                Box::new( ast::Sin {
-                    arg: arg
-                })
-            }
-            "sqrt" | "SQRT" => {
-                Box::new( ast::Sqrt {
-                    arg: arg
-                })
-            }
-            "cos" | "cosine" => {
-                Box::new( ast::Cos {
-                    arg: arg
-                })
-            }
-            "tan" | "tangent" => {
-                Box::new( ast::Tan {
-                    arg: arg
-                })
-            }
-            "fact" => {
-                Box::new( ast::Factorial {
-                    arg: arg
-                })
-            } 
-            "print" => {
-                Box::new( ast::Print {
-                    arg: arg
-                })
+    /* parses a parenthesised, comma separated argument list */
+    pub fn arguments(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.open(LPAREN)?;
+        let mut args = Vec::new();
+        if self.peek_token()? == RPAREN {
+            return self.close(RPAREN).map(|_| args);
+        }
+        loop {
+            args.push(self.expr(1)?);
+            if self.peek_token()? == COMMA {
+                self.next_token()?;
+            } else {
+                break;
             }
-            "-" => {
-                Box::new( ast::Negate {
-                    arg: arg
-                })
+        }
+        self.close(RPAREN)?;
+        Ok(args)
+    }
+
+    /* parses the `(x, y)` parameter list of a function definition */
+    pub fn parameters(&mut self) -> Result<Vec<String>, ParseError> {
+        self.open(LPAREN)?;
+        let mut params: Vec<String> = Vec::new();
+        if self.peek_token()? == RPAREN {
+            return self.close(RPAREN).map(|_| params);
+        }
+        loop {
+            self.next_token()?;
+            match self.current.node.clone() {
+                SYMBOL(p) if params.contains(&p) => {
+                    return Err(ParseError::new(
+                        format!("duplicate parameter '{}'", p), self.current.span));
+                }
+                SYMBOL(p) => params.push(p),
+                t => {
+                    return Err(ParseError::new(
+                        format!("expected a parameter name but found {}", t.describe()),
+                        self.current.span));
+                }
             }
-            _ => {
-                panic!("unrecognized function!");
+            if self.peek_token()? == COMMA {
+                self.next_token()?;
+            } else {
+                break;
             }
         }
+        self.close(RPAREN)?;
+        Ok(params)
     }
 }
 
 impl Parser {
-    pub fn expect(&mut self, tok: char) -> Result<(), String> {
+    pub fn expect(&mut self, tok: token::Token) -> Result<(), ParseError> {
         self.next_token()?;
-        if self.current.to_char() != tok {
-            return Err(format!("expected {:?} but found {}", tok, self.current.to_char()));
+        if self.current.node != tok {
+            return Err(ParseError::new(
+                format!("expected {} but found {}", tok.describe(), self.current.node.describe()),
+                self.current.span));
         }
         Ok(())
     }
-    pub fn peek_token(&mut self) -> Result<token::Token, String> {
+    /* `expect` for an opening parenthesis, after which newlines are skipped */
+    fn open(&mut self, tok: token::Token) -> Result<(), ParseError> {
+        self.expect(tok)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn close(&mut self, tok: token::Token) -> Result<(), ParseError> {
+        self.expect(tok)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /* rejects anything left over after a complete expression */
+    fn expect_end(&mut self, is_end: fn(&token::Token) -> bool) -> Result<(), ParseError> {
+        let next = self.peek()?;
+        if !is_end(&next.node) {
+            return Err(ParseError::new(
+                format!("unexpected {} after expression", next.node.describe()),
+                next.span));
+        }
+        Ok(())
+    }
+    pub fn peek_token(&mut self) -> Result<token::Token, ParseError> {
+        Ok(self.peek()?.node.clone())
+    }
+    pub fn peek_span(&mut self) -> Result<Span, ParseError> {
+        Ok(self.peek()?.span)
+    }
+    fn peek(&mut self) -> Result<&Spanned<token::Token>, ParseError> {
         if self.peeked.is_none() {
-            self.peeked = Some(self.lexer.next_token()?);
+            self.peeked = match self.after.take() {
+                Some(next) => Some(next),
+                None => Some(self.lex()?),
+            };
         }
-        Ok(self.peeked.clone().unwrap())
+        Ok(self.peeked.as_ref().unwrap())
     }
-    pub fn next_token(&mut self) -> Result<(), String> {
-        match self.peeked {
-            Some(ref mut pk) => {
-                self.current = pk.clone();
-            }
-            None => {
-                self.current = self.lexer.next_token()?;
+    /* the token after the peeked one */
+    fn peek_after(&mut self) -> Result<token::Token, ParseError> {
+        self.peek()?;
+        if self.after.is_none() {
+            self.after = Some(self.lex()?);
+        }
+        Ok(self.after.as_ref().unwrap().node.clone())
+    }
+    fn lex(&mut self) -> Result<Spanned<token::Token>, ParseError> {
+        loop {
+            let next = self.lexer.next_token()?;
+            if self.depth == 0 || next.node != NEWLINE {
+                return Ok(next);
             }
         }
-        self.peeked = None;
+    }
+    pub fn next_token(&mut self) -> Result<(), ParseError> {
+        self.peek()?;
+        self.current = self.peeked.take().unwrap();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* the shape of `e` as an s-expression, without spans */
+    fn sexp(e: &Expr) -> String {
+        match e {
+            Expr::Num { literal, .. } => literal.clone(),
+            Expr::Var { name, .. } => name.clone(),
+            Expr::Unit { name, .. } => format!("[{}]", name),
+            Expr::Unary { op, arg, .. } => format!("({} {})", op.symbol(), sexp(arg)),
+            Expr::Binary { op, lhs, rhs, .. } => format!("({} {} {})", op.symbol(), sexp(lhs), sexp(rhs)),
+            Expr::Logical { op, lhs, rhs, .. } => {
+                let op = if *op == LogicalOp::And { "and" } else { "or" };
+                format!("({} {} {})", op, sexp(lhs), sexp(rhs))
+            }
+            Expr::If { cond, then, otherwise, .. } => {
+                format!("(if {} {} {})", sexp(cond), sexp(then), sexp(otherwise))
+            }
+            Expr::Angle { arg, unit, .. } => format!("({} {})", unit.suffix(), sexp(arg)),
+            Expr::Convert { arg, target, .. } => format!("(to {} {})", sexp(arg), sexp(target)),
+            Expr::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(sexp).collect();
+                format!("({} {})", name, args.join(" "))
+            }
+            Expr::Assign { name, value, .. } => format!("(let {} {})", name, sexp(value)),
+            Expr::FnDef { name, params, body, .. } => {
+                format!("(let ({} {}) {})", name, params.join(" "), sexp(body))
+            }
+        }
+    }
+
+    fn parse(input: &str) -> String {
+        sexp(&Parser::new(input).parse().unwrap())
+    }
+
+    #[test]
+    fn binary_precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(parse("1 * 2 + 3"), "(+ (* 1 2) 3)");
+        assert_eq!(parse("8 / 4 % 3"), "(% (/ 8 4) 3)");
+        assert_eq!(parse("1 << 2 + 3"), "(<< 1 (+ 2 3))");
+        assert_eq!(parse("1 | 2 xor 3 & 4"), "(| 1 (xor 2 (& 3 4)))");
+        assert_eq!(parse("1 + 2 < 4 and 5 > 3 or 0"), "(or (and (< (+ 1 2) 4) (> 5 3)) 0)");
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(parse("1 - 2 - 3"), "(- (- 1 2) 3)");
+        assert_eq!(parse("8 / 4 / 2"), "(/ (/ 8 4) 2)");
+        assert_eq!(parse("2 ^ 3 ^ 2"), "(^ 2 (^ 3 2))");
+        assert_eq!(parse("a ? b : c ? d : e"), "(if a b (if c d e))");
+    }
+
+    #[test]
+    fn prefix_and_postfix() {
+        assert_eq!(parse("-2 ^ 2"), "(- (^ 2 2))");
+        assert_eq!(parse("-2 * 3"), "(* (- 2) 3)");
+        assert_eq!(parse("-3!"), "(- (! 3))");
+        assert_eq!(parse("2 ^ 3!"), "(^ 2 (! 3))");
+        assert_eq!(parse("not a == b"), "(not (== a b))");
+    }
+
+    #[test]
+    fn percent_is_modulo_only_before_an_operand() {
+        assert_eq!(parse("50%"), "(% 50)");
+        assert_eq!(parse("7 % 3"), "(% 7 3)");
+        assert_eq!(parse("10 % -3"), "(% 10 (- 3))");
+        assert_eq!(parse("7 % +2"), "(% 7 (+ 2))");
+        assert_eq!(parse("2^10 % 7"), "(% (^ 2 10) 7)");
+        assert_eq!(parse("200 + 10%"), "(+ 200 (% 10))");
+        assert_eq!(parse("50% * 4"), "(* (% 50) 4)");
+        assert_eq!(parse("(\n50%\n) - 1"), "(- (% 50) 1)");
+    }
+
+    #[test]
+    fn angle_suffixes_apply_to_the_whole_term() {
+        assert_eq!(parse("30deg"), "(deg 30)");
+        assert_eq!(parse("sin(pi/6 rad)"), "(sin (rad (/ pi 6)))");
+        assert_eq!(parse("2 * 30 deg"), "(deg (* 2 30))");
+        assert_eq!(parse("-30 deg"), "(deg (- 30))");
+        assert_eq!(parse("2^3 deg"), "(deg (^ 2 3))");
+        assert_eq!(parse("90 deg + pi/2 rad"), "(+ (deg 90) (rad (/ pi 2)))");
+        assert_eq!(parse("x deg * 2"), "(* (deg x) 2)");
+    }
+
+    #[test]
+    fn units_follow_numbers_and_to() {
+        assert_eq!(parse("5 h"), "(* 5 [h])");
+        assert_eq!(parse("3 ft^2"), "(* 3 (^ [ft] 2))");
+        assert_eq!(parse("100 km/h"), "(* 100 (/ [km] [h]))");
+        assert_eq!(parse("10 m / 2 s"), "(/ (* 10 [m]) (* 2 [s]))");
+        assert_eq!(parse("x to km/h"), "(to x (/ [km] [h]))");
+        assert_eq!(parse("h * c"), "(* h c)");
+        assert_eq!(parse("2 min"), "(* 2 [min])");
+        assert_eq!(parse("2 min(3, 4)"), "(* 2 (min 3 4))");
+    }
+
+    #[test]
+    fn calls_and_definitions() {
+        assert_eq!(parse("max(1, 2 + 3)"), "(max 1 (+ 2 3))");
+        assert_eq!(parse("let x = 1 + 2"), "(let x (+ 1 2))");
+        assert_eq!(parse("let f(a, b) = a * b"), "(let (f a b) (* a b))");
+        assert_eq!(parse("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+    }
+
+    fn parse_error(input: &str) -> ParseError {
+        Parser::new(input).parse_program().unwrap_err()
+    }
+
+    #[test]
+    fn incomplete_input_is_an_error() {
+        for input in ["2 *", "5 /", "2 +", "let x =", "(1 + 2", "-"] {
+            let e = parse_error(input);
+            assert!(e.message.starts_with("expected"), "{}: {}", input, e.message);
+        }
+        let e = parse_error("2 *");
+        assert_eq!((e.message.as_str(), e.span), ("expected an operand", Span::new(3, 3)));
+        assert_eq!(parse_error("1 + )").message, "expected an operand but found ')'");
+    }
+
+    #[test]
+    fn newlines_inside_parentheses() {
+        assert_eq!(parse("(1 +\n 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(parse("max(1,\n 2)"), "(max 1 2)");
+        assert!(Parser::new("1 +\n2").parse_program().is_err());
+        assert_eq!(Parser::new("(1)\n2").parse_program().unwrap().statements.len(), 2);
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        assert_eq!(parse("2x^2"), "(* 2 (^ x 2))");
+        assert_eq!(parse("1/2x"), "(* (/ 1 2) x)");
+        assert!(Parser::new("2x").strict().parse().is_err());
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_an_error() {
+        let depth = MAX_PARSE_DEPTH - 1;
+        let input = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parse(&input), "1");
+        let deep = [
+            format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000)),
+            format!("{}1", "-".repeat(10_000)),
+            format!("{}1{}", "sin(".repeat(10_000), ")".repeat(10_000)),
+            format!("{}2", "2^".repeat(10_000)),
+        ];
+        for input in deep {
+            assert_eq!(parse_error(&input).message, "expression is nested too deeply");
+        }
+    }
+}
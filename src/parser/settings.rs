@@ -0,0 +1,372 @@
+use std::fmt;
+use std::str::FromStr;
+
+/* how numbers are represented while evaluating */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    /* 64-bit binary floating point */
+    #[default]
+    Float,
+    /* arbitrary-precision decimal rounded to `Settings::precision` digits */
+    Decimal,
+    /* exact fractions; transcendental functions fall back to floating point */
+    Rational,
+    /* floating point that turns square roots, logarithms and powers of
+       negative numbers into complex results instead of domain errors */
+    Complex,
+    /* fixed-width integers that wrap around, for bitwise work */
+    Integer,
+}
+
+impl NumberMode {
+    pub const ALL: [NumberMode; 5] = [
+        NumberMode::Float,
+        NumberMode::Decimal,
+        NumberMode::Rational,
+        NumberMode::Complex,
+        NumberMode::Integer,
+    ];
+
+    /* the mode after this one, wrapping around; used by toggle buttons */
+    pub fn next(self) -> NumberMode {
+        let i = NumberMode::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        NumberMode::ALL[(i + 1) % NumberMode::ALL.len()]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            NumberMode::Float => "FLT",
+            NumberMode::Decimal => "DEC",
+            NumberMode::Rational => "RAT",
+            NumberMode::Complex => "CPX",
+            NumberMode::Integer => "INT",
+        }
+    }
+}
+
+impl fmt::Display for NumberMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NumberMode::Float => write!(f, "float"),
+            NumberMode::Decimal => write!(f, "decimal"),
+            NumberMode::Rational => write!(f, "rational"),
+            NumberMode::Complex => write!(f, "complex"),
+            NumberMode::Integer => write!(f, "integer"),
+        }
+    }
+}
+
+impl FromStr for NumberMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NumberMode, String> {
+        match s {
+            "float" => Ok(NumberMode::Float),
+            "decimal" => Ok(NumberMode::Decimal),
+            "rational" => Ok(NumberMode::Rational),
+            "complex" => Ok(NumberMode::Complex),
+            "integer" => Ok(NumberMode::Integer),
+            _ => Err(format!("unknown number mode '{}'", s)),
+        }
+    }
+}
+
+/* how exact fractions are displayed */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FractionStyle {
+    /* 3/2 */
+    #[default]
+    Fraction,
+    /* 1 1/2 */
+    Mixed,
+    /* 1.5 */
+    Decimal,
+}
+
+impl FractionStyle {
+    pub const ALL: [FractionStyle; 3] = [FractionStyle::Fraction, FractionStyle::Mixed, FractionStyle::Decimal];
+
+    pub fn next(self) -> FractionStyle {
+        let i = FractionStyle::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        FractionStyle::ALL[(i + 1) % FractionStyle::ALL.len()]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            FractionStyle::Fraction => "a/b",
+            FractionStyle::Mixed => "a b/c",
+            FractionStyle::Decimal => "0.5",
+        }
+    }
+}
+
+impl fmt::Display for FractionStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FractionStyle::Fraction => write!(f, "fraction"),
+            FractionStyle::Mixed => write!(f, "mixed"),
+            FractionStyle::Decimal => write!(f, "decimal"),
+        }
+    }
+}
+
+impl FromStr for FractionStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FractionStyle, String> {
+        match s {
+            "fraction" => Ok(FractionStyle::Fraction),
+            "mixed" => Ok(FractionStyle::Mixed),
+            "decimal" => Ok(FractionStyle::Decimal),
+            _ => Err(format!("unknown fraction style '{}'", s)),
+        }
+    }
+}
+
+/* how complex numbers are displayed */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ComplexStyle {
+    /* 3 + 4i */
+    #[default]
+    Rectangular,
+    /* 5 ∠ 0.9273 */
+    Polar,
+}
+
+impl ComplexStyle {
+    pub const ALL: [ComplexStyle; 2] = [ComplexStyle::Rectangular, ComplexStyle::Polar];
+
+    pub fn next(self) -> ComplexStyle {
+        let i = ComplexStyle::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        ComplexStyle::ALL[(i + 1) % ComplexStyle::ALL.len()]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            ComplexStyle::Rectangular => "a+bi",
+            ComplexStyle::Polar => "r∠θ",
+        }
+    }
+}
+
+impl fmt::Display for ComplexStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ComplexStyle::Rectangular => write!(f, "rectangular"),
+            ComplexStyle::Polar => write!(f, "polar"),
+        }
+    }
+}
+
+impl FromStr for ComplexStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ComplexStyle, String> {
+        match s {
+            "rectangular" => Ok(ComplexStyle::Rectangular),
+            "polar" => Ok(ComplexStyle::Polar),
+            _ => Err(format!("unknown complex style '{}'", s)),
+        }
+    }
+}
+
+/* word size and signedness of integer mode */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegerType {
+    pub bits: u32,
+    pub signed: bool,
+}
+
+impl IntegerType {
+    pub const ALL: [IntegerType; 8] = [
+        IntegerType { bits: 8, signed: true },
+        IntegerType { bits: 8, signed: false },
+        IntegerType { bits: 16, signed: true },
+        IntegerType { bits: 16, signed: false },
+        IntegerType { bits: 32, signed: true },
+        IntegerType { bits: 32, signed: false },
+        IntegerType { bits: 64, signed: true },
+        IntegerType { bits: 64, signed: false },
+    ];
+
+    pub fn next(self) -> IntegerType {
+        let i = IntegerType::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        IntegerType::ALL[(i + 1) % IntegerType::ALL.len()]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match (self.bits, self.signed) {
+            (8, true) => "i8",
+            (8, false) => "u8",
+            (16, true) => "i16",
+            (16, false) => "u16",
+            (32, true) => "i32",
+            (32, false) => "u32",
+            (64, true) => "i64",
+            (64, false) => "u64",
+            _ => "int",
+        }
+    }
+
+    /* reduces `x` modulo 2^bits into the range of this type */
+    pub fn wrap(&self, x: i128) -> i128 {
+        if self.bits >= 128 {
+            return x;
+        }
+        let v = (x as u128) & self.mask();
+        if self.signed && (v >> (self.bits - 1)) & 1 == 1 {
+            v as i128 - (1i128 << self.bits)
+        } else {
+            v as i128
+        }
+    }
+
+    /* the low `bits` bits set */
+    pub fn mask(&self) -> u128 {
+        if self.bits >= 128 { u128::MAX } else { (1u128 << self.bits) - 1 }
+    }
+}
+
+impl Default for IntegerType {
+    fn default() -> IntegerType {
+        IntegerType { bits: 64, signed: true }
+    }
+}
+
+impl fmt::Display for IntegerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+impl FromStr for IntegerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IntegerType, String> {
+        IntegerType::ALL.iter()
+            .find(|t| t.label() == s)
+            .copied()
+            .ok_or(format!("unknown integer type '{}'", s))
+    }
+}
+
+/* the unit trigonometric functions take and return angles in */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    Degrees,
+    Gradians,
+}
+
+impl AngleUnit {
+    pub const ALL: [AngleUnit; 3] = [AngleUnit::Radians, AngleUnit::Degrees, AngleUnit::Gradians];
+
+    pub fn next(self) -> AngleUnit {
+        let i = AngleUnit::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        AngleUnit::ALL[(i + 1) % AngleUnit::ALL.len()]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            AngleUnit::Radians => "RAD",
+            AngleUnit::Degrees => "DEG",
+            AngleUnit::Gradians => "GRAD",
+        }
+    }
+
+    /* the unit named by an angle suffix such as `30deg` */
+    pub fn from_suffix(s: &str) -> Option<AngleUnit> {
+        match s {
+            "rad" => Some(AngleUnit::Radians),
+            "deg" => Some(AngleUnit::Degrees),
+            "grad" => Some(AngleUnit::Gradians),
+            _ => None,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            AngleUnit::Radians => "rad",
+            AngleUnit::Degrees => "deg",
+            AngleUnit::Gradians => "grad",
+        }
+    }
+
+    /* size of a full turn in this unit */
+    pub fn turn(&self) -> f64 {
+        match *self {
+            AngleUnit::Radians => std::f64::consts::TAU,
+            AngleUnit::Degrees => 360.0,
+            AngleUnit::Gradians => 400.0,
+        }
+    }
+
+    pub fn to_radians(&self, x: f64) -> f64 {
+        match *self {
+            AngleUnit::Radians => x,
+            AngleUnit::Degrees => x.to_radians(),
+            AngleUnit::Gradians => x * std::f64::consts::PI / 200.0,
+        }
+    }
+
+    pub fn from_radians(&self, x: f64) -> f64 {
+        match *self {
+            AngleUnit::Radians => x,
+            AngleUnit::Degrees => x.to_degrees(),
+            AngleUnit::Gradians => x * 200.0 / std::f64::consts::PI,
+        }
+    }
+}
+
+impl fmt::Display for AngleUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AngleUnit::Radians => write!(f, "radians"),
+            AngleUnit::Degrees => write!(f, "degrees"),
+            AngleUnit::Gradians => write!(f, "gradians"),
+        }
+    }
+}
+
+impl FromStr for AngleUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AngleUnit, String> {
+        match s {
+            "radians" | "rad" => Ok(AngleUnit::Radians),
+            "degrees" | "deg" => Ok(AngleUnit::Degrees),
+            "gradians" | "grad" => Ok(AngleUnit::Gradians),
+            _ => Err(format!("unknown angle unit '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub mode: NumberMode,
+    /* significant digits kept by decimal arithmetic */
+    pub precision: u64,
+    pub fraction: FractionStyle,
+    pub complex: ComplexStyle,
+    pub angle: AngleUnit,
+    /* whether `2x` means `2*x`; strict mode turns this off */
+    pub implicit_multiplication: bool,
+    pub integer: IntegerType,
+}
+
+pub const DEFAULT_PRECISION: u64 = 50;
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            mode: NumberMode::Float,
+            precision: DEFAULT_PRECISION,
+            fraction: FractionStyle::Fraction,
+            complex: ComplexStyle::Rectangular,
+            angle: AngleUnit::Radians,
+            implicit_multiplication: true,
+            integer: IntegerType::default(),
+        }
+    }
+}
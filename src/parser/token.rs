@@ -1,10 +1,14 @@
 use self::Token::*;
 
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug,PartialEq, Clone)]
 pub enum Token {
     LPAREN,
     RPAREN,
+    COMMA,
+    SEMICOLON,
+    NEWLINE,
     ADD,
     SUB,
     MUL,
@@ -12,15 +16,55 @@ pub enum Token {
     MOD,
     CARET,
     EQUALS,
-    NUMBER(f64),
+    /* bitwise `&`, `|`, `~`, `<<` and `>>`; xor is the keyword `xor` */
+    AMP,
+    PIPE,
+    TILDE,
+    SHL,
+    SHR,
+    /* comparisons and the `cond ? a : b` conditional; `and`, `or` and `not` are keywords */
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+    QUESTION,
+    COLON,
+    /* postfix operators: `5!` and `7!!`; `50%` is a MOD with no operand after it */
+    BANG,
+    DBANG,
+    NUMBER(String),
     SYMBOL(String),
     EOF
 }
 
+/* binding power of prefix `-` and `+`: tighter than `*` but looser than `^`,
+   so -2^2 is -(2^2) while -2*3 is (-2)*3 */
+pub const PREFIX_PREC: usize = 25;
+
+/* binding power of the angle suffixes `deg`, `rad` and `grad`: looser than `*`
+   and `/` but tighter than `+`, so pi/6 rad is (pi/6) rad and 90 deg + pi rad
+   adds two angles */
+pub const SUFFIX_PREC: usize = 15;
+
+/* binding power of `not`: looser than comparisons but tighter than `and`,
+   so `not a == b` is `not (a == b)` */
+pub const NOT_PREC: usize = 4;
+
 impl Token {
     /* returns (prec, associativity) where 0 is left and 1 is right*/
     pub fn info(&self) -> Option<(usize, usize)> {
         match *self {
+            QUESTION => Some((1, 1)),
+            SYMBOL(ref s) if s == "to" || s == "in" => Some((1, 0)),
+            SYMBOL(ref s) if s == "or" => Some((2, 0)),
+            SYMBOL(ref s) if s == "and" => Some((3, 0)),
+            EQ | NE | LT | LE | GT | GE => Some((5, 0)),
+            PIPE => Some((6, 0)),
+            SYMBOL(ref s) if s == "xor" => Some((7, 0)),
+            AMP => Some((8, 0)),
+            SHL | SHR => Some((9, 0)),
             ADD | SUB => Some((10, 0)),
             MUL | DIV | MOD => Some((20, 0)),
             CARET => Some((30, 1)),
@@ -32,6 +76,9 @@ impl Token {
         match *self {
             LPAREN => '(',
             RPAREN => ')',
+            COMMA => ',',
+            SEMICOLON => ';',
+            NEWLINE => '\n',
             ADD => '+',
             SUB => '-',
             MUL => '*',
@@ -39,16 +86,51 @@ impl Token {
             CARET => '^',
             MOD => '%',
             EQUALS => '=',
+            AMP => '&',
+            PIPE => '|',
+            TILDE => '~',
+            SHL | LT | LE => '<',
+            SHR | GT | GE => '>',
+            EQ => '=',
+            NE => '!',
+            QUESTION => '?',
+            COLON => ':',
+            BANG | DBANG => '!',
             EOF => 'E',
             NUMBER(_) => 'N',
             SYMBOL(_) => 'S',
         }
     }
+
+    /* human readable form used in error messages */
+    pub fn describe(&self) -> String {
+        match *self {
+            EOF => "end of input".to_string(),
+            NEWLINE => "end of line".to_string(),
+            DBANG => "'!!'".to_string(),
+            SHL => "'<<'".to_string(),
+            SHR => "'>>'".to_string(),
+            EQ => "'=='".to_string(),
+            NE => "'!='".to_string(),
+            LE => "'<='".to_string(),
+            GE => "'>='".to_string(),
+            NUMBER(ref n) => format!("number {}", n),
+            SYMBOL(ref s) => format!("'{}'", s),
+            _ => format!("'{}'", self.to_char()),
+        }
+    }
+}
+
+/* tokens that can begin an operand of an implicit multiplication */
+pub fn starts_operand(t: &Token) -> bool {
+    matches!(t, &LPAREN | &SYMBOL(_))
 }
 
 pub fn is_eof(t: &Token) -> bool{
-    match t {
-        &EOF => true,
-        _ => false
-    }
+    matches!(t, &EOF)
+}
+
+/* tokens that may end a statement in a program */
+pub fn is_separator(t: &Token) -> bool {
+    matches!(t, &SEMICOLON | &NEWLINE)
 }
@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::parser::ast::BinOp;
+use crate::parser::error::{check_overflow, EvalError};
+use crate::parser::value::Value;
+
+/* the SI base units and money, in the order of `Dimension`'s exponents */
+const BASE_UNITS: [&str; 8] = ["m", "kg", "s", "A", "K", "mol", "cd", "¤"];
+
+/* where money sits in a `Dimension` */
+pub const CURRENCY: Dimension = Dimension([0, 0, 0, 0, 0, 0, 0, 1]);
+
+/* the currencies the default rates service quotes; naming one before any
+   rates are loaded is reported as such rather than as an unknown variable */
+pub const CURRENCY_CODES: [&str; 31] = [
+    "AUD", "BGN", "BRL", "CAD", "CHF", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD",
+    "HUF", "IDR", "ILS", "INR", "ISK", "JPY", "KRW", "MXN", "MYR", "NOK", "NZD",
+    "PHP", "PLN", "RON", "SEK", "SGD", "THB", "TRY", "USD", "ZAR",
+];
+
+/* name, size in SI base units, exponents of the base units and description */
+const BUILTIN: [(&str, f64, [i32; 8], &str); 57] = [
+    ("m", 1.0, [1, 0, 0, 0, 0, 0, 0, 0], "metre"),
+    ("km", 1e3, [1, 0, 0, 0, 0, 0, 0, 0], "kilometre"),
+    ("cm", 1e-2, [1, 0, 0, 0, 0, 0, 0, 0], "centimetre"),
+    ("mm", 1e-3, [1, 0, 0, 0, 0, 0, 0, 0], "millimetre"),
+    ("um", 1e-6, [1, 0, 0, 0, 0, 0, 0, 0], "micrometre"),
+    ("nm", 1e-9, [1, 0, 0, 0, 0, 0, 0, 0], "nanometre"),
+    ("inch", 0.0254, [1, 0, 0, 0, 0, 0, 0, 0], "inch"),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0, 0, 0], "foot"),
+    ("yd", 0.9144, [1, 0, 0, 0, 0, 0, 0, 0], "yard"),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0, 0, 0], "mile"),
+    ("nmi", 1852.0, [1, 0, 0, 0, 0, 0, 0, 0], "nautical mile"),
+    ("au", 149_597_870_700.0, [1, 0, 0, 0, 0, 0, 0, 0], "astronomical unit"),
+    ("ly", 9_460_730_472_580_800.0, [1, 0, 0, 0, 0, 0, 0, 0], "light year"),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0, 0, 0], "kilogram"),
+    ("g", 1e-3, [0, 1, 0, 0, 0, 0, 0, 0], "gram"),
+    ("mg", 1e-6, [0, 1, 0, 0, 0, 0, 0, 0], "milligram"),
+    ("tonne", 1e3, [0, 1, 0, 0, 0, 0, 0, 0], "metric ton"),
+    ("lb", 0.453_592_37, [0, 1, 0, 0, 0, 0, 0, 0], "pound"),
+    ("oz", 0.028_349_523_125, [0, 1, 0, 0, 0, 0, 0, 0], "ounce"),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0, 0, 0], "second"),
+    ("ms", 1e-3, [0, 0, 1, 0, 0, 0, 0, 0], "millisecond"),
+    ("min", 60.0, [0, 0, 1, 0, 0, 0, 0, 0], "minute"),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0, 0, 0], "hour"),
+    ("day", 86400.0, [0, 0, 1, 0, 0, 0, 0, 0], "day"),
+    ("week", 604_800.0, [0, 0, 1, 0, 0, 0, 0, 0], "week"),
+    ("yr", 31_557_600.0, [0, 0, 1, 0, 0, 0, 0, 0], "Julian year"),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0, 0], "ampere"),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0, 0, 0], "kelvin"),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0, 0], "mole"),
+    ("cd", 1.0, [0, 0, 0, 0, 0, 0, 1, 0], "candela"),
+    ("mph", 0.447_04, [1, 0, -1, 0, 0, 0, 0, 0], "miles per hour"),
+    ("knot", 1852.0 / 3600.0, [1, 0, -1, 0, 0, 0, 0, 0], "nautical miles per hour"),
+    ("ha", 1e4, [2, 0, 0, 0, 0, 0, 0, 0], "hectare"),
+    ("acre", 4_046.856_422_4, [2, 0, 0, 0, 0, 0, 0, 0], "acre"),
+    ("L", 1e-3, [3, 0, 0, 0, 0, 0, 0, 0], "litre"),
+    ("mL", 1e-6, [3, 0, 0, 0, 0, 0, 0, 0], "millilitre"),
+    ("gal", 3.785_411_784e-3, [3, 0, 0, 0, 0, 0, 0, 0], "US gallon"),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0, 0], "hertz"),
+    ("N", 1.0, [1, 1, -2, 0, 0, 0, 0, 0], "newton"),
+    ("Pa", 1.0, [-1, 1, -2, 0, 0, 0, 0, 0], "pascal"),
+    ("kPa", 1e3, [-1, 1, -2, 0, 0, 0, 0, 0], "kilopascal"),
+    ("bar", 1e5, [-1, 1, -2, 0, 0, 0, 0, 0], "bar"),
+    ("atm", 101_325.0, [-1, 1, -2, 0, 0, 0, 0, 0], "standard atmosphere"),
+    ("psi", 6_894.757_293_168, [-1, 1, -2, 0, 0, 0, 0, 0], "pound per square inch"),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0, 0, 0], "joule"),
+    ("kJ", 1e3, [2, 1, -2, 0, 0, 0, 0, 0], "kilojoule"),
+    ("cal", 4.184, [2, 1, -2, 0, 0, 0, 0, 0], "calorie"),
+    ("kcal", 4184.0, [2, 1, -2, 0, 0, 0, 0, 0], "kilocalorie"),
+    ("Wh", 3600.0, [2, 1, -2, 0, 0, 0, 0, 0], "watt hour"),
+    ("kWh", 3.6e6, [2, 1, -2, 0, 0, 0, 0, 0], "kilowatt hour"),
+    ("eV", 1.602_176_634e-19, [2, 1, -2, 0, 0, 0, 0, 0], "electronvolt"),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0, 0, 0], "watt"),
+    ("kW", 1e3, [2, 1, -3, 0, 0, 0, 0, 0], "kilowatt"),
+    ("hp", 745.699_871_582_270_2, [2, 1, -3, 0, 0, 0, 0, 0], "mechanical horsepower"),
+    ("C", 1.0, [0, 0, 1, 1, 0, 0, 0, 0], "coulomb"),
+    ("V", 1.0, [2, 1, -3, -1, 0, 0, 0, 0], "volt"),
+    ("ohm", 1.0, [2, 1, -3, -2, 0, 0, 0, 0], "ohm"),
+];
+
+/* exponents of the SI base units m, kg, s, A, K, mol and cd, and of money,
+   whose unit is whichever currency the exchange rates are quoted against */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dimension(pub [i32; 8]);
+
+impl Dimension {
+    pub fn is_none(&self) -> bool {
+        self.0.iter().all(|&e| e == 0)
+    }
+
+    /* the dimension of self * other^power */
+    fn times(self, other: Dimension, power: i32) -> Dimension {
+        let mut exps = self.0;
+        for (e, o) in exps.iter_mut().zip(other.0) {
+            *e += o * power;
+        }
+        Dimension(exps)
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = Unit {
+            parts: BASE_UNITS.iter()
+                .zip(self.0)
+                .map(|(name, power)| UnitPart { name: name.to_string(), factor: 1.0, dim: Dimension::default(), power })
+                .filter(|p| p.power != 0)
+                .collect(),
+        };
+        write!(f, "{}", unit)
+    }
+}
+
+/* an entry of the unit table */
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitDef {
+    pub name: String,
+    /* size in SI base units */
+    pub factor: f64,
+    pub dim: Dimension,
+    pub description: String,
+}
+
+/* one named unit raised to a power, such as the h^-1 in km/h */
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitPart {
+    pub name: String,
+    pub factor: f64,
+    pub dim: Dimension,
+    pub power: i32,
+}
+
+/* a product of named units, kept as written so results are shown in the
+   units they were entered in */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unit {
+    pub parts: Vec<UnitPart>,
+}
+
+impl Unit {
+    pub fn named(def: &UnitDef) -> Unit {
+        Unit {
+            parts: vec![UnitPart { name: def.name.clone(), factor: def.factor, dim: def.dim, power: 1 }],
+        }
+    }
+
+    /* size in SI base units */
+    pub fn factor(&self) -> f64 {
+        self.parts.iter().map(|p| p.factor.powi(p.power)).product()
+    }
+
+    pub fn dim(&self) -> Dimension {
+        self.parts.iter().fold(Dimension::default(), |d, p| d.times(p.dim, p.power))
+    }
+
+    /* self * other^sign, along with the factor the magnitude has to be scaled by;
+       a part of `other` with the same dimension as one of ours is converted into
+       it, so m * ft is m^2 and km/h * s is km, scaled by 1/3600 */
+    fn combine(&self, other: &Unit, sign: i32) -> (Unit, f64) {
+        let mut parts = self.parts.clone();
+        let mut scale = 1.0;
+        for part in &other.parts {
+            let power = part.power * sign;
+            let same = parts.iter().position(|p| p.name == part.name)
+                .or_else(|| parts.iter().position(|p| p.dim == part.dim));
+            match same {
+                Some(i) => {
+                    scale *= (part.factor / parts[i].factor).powi(power);
+                    parts[i].power += power;
+                }
+                None => parts.push(UnitPart { power, ..part.clone() }),
+            }
+        }
+        parts.retain(|p| p.power != 0);
+        (Unit { parts }, scale)
+    }
+}
+
+/* `km/h`, `m^2` or `J/(mol K)`; empty for a plain number */
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |parts: Vec<(&str, i32)>| parts.iter()
+            .map(|&(name, power)| if power == 1 { name.to_string() } else { format!("{}^{}", name, power) })
+            .collect::<Vec<String>>()
+            .join(" ");
+        let above: Vec<(&str, i32)> = self.parts.iter().filter(|p| p.power > 0).map(|p| (&p.name[..], p.power)).collect();
+        let below: Vec<(&str, i32)> = self.parts.iter().filter(|p| p.power < 0).map(|p| (&p.name[..], -p.power)).collect();
+        if above.is_empty() {
+            // s^-1 rather than 1/s
+            let negated = below.iter().map(|&(name, power)| (name, -power)).collect();
+            return write!(f, "{}", show(negated));
+        }
+        match below.len() {
+            0 => write!(f, "{}", show(above)),
+            1 => write!(f, "{}/{}", show(above), show(below)),
+            _ => write!(f, "{}/({})", show(above), show(below)),
+        }
+    }
+}
+
+/* a number with a unit; always computed in floating point */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    /* magnitude in `unit` */
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /* the magnitude expressed in `target`, which must have the same dimension */
+    pub fn convert(&self, target: &Unit) -> Result<f64, EvalError> {
+        if self.unit.dim() != target.dim() {
+            return Err(incompatible(&self.unit, target));
+        }
+        Ok(self.value * self.unit.factor() / target.factor())
+    }
+
+    /* units that cancel out leave a plain number */
+    pub fn into_value(self) -> Value {
+        if self.unit.dim().is_none() {
+            Value::Float(self.value * self.unit.factor())
+        } else {
+            Value::Quantity(self)
+        }
+    }
+
+    /* a plain number as a quantity without units */
+    pub fn of(v: Value) -> Result<Quantity, EvalError> {
+        match v {
+            Value::Quantity(q) => Ok(q),
+            Value::Complex(_) => Err(EvalError::ComplexArgument("a quantity with units".to_string())),
+            v => Ok(Quantity { value: v.to_f64(), unit: Unit::default() }),
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // -0 is an artifact of negation and only confuses
+        let value = if self.value == 0.0 { 0.0 } else { self.value };
+        write!(f, "{} {}", value, self.unit)
+    }
+}
+
+fn incompatible(l: &Unit, r: &Unit) -> EvalError {
+    let name = |u: &Unit| if u.parts.is_empty() { "no unit".to_string() } else { u.to_string() };
+    EvalError::IncompatibleUnits(name(l), name(r))
+}
+
+/* arithmetic where at least one operand has units; sums, remainders and
+   comparisons need matching dimensions and answer in the left operand's unit */
+pub fn quantity_binary(op: BinOp, l: Value, r: Value) -> Result<Value, EvalError> {
+    let (l, r) = (Quantity::of(l)?, Quantity::of(r)?);
+    match op {
+        BinOp::Mul | BinOp::Div => {
+            let sign = if op == BinOp::Mul { 1 } else { -1 };
+            let (unit, scale) = l.unit.combine(&r.unit, sign);
+            let value = if sign == 1 { l.value * r.value } else { l.value / r.value } * scale;
+            check_overflow(value, &[l.value, r.value])?;
+            Ok(Quantity { value, unit }.into_value())
+        }
+        BinOp::Pow => {
+            if !r.unit.parts.is_empty() {
+                return Err(incompatible(&l.unit, &r.unit));
+            }
+            // only powers that leave whole exponents, so (4 m^2)^0.5 is 2 m
+            let mut parts = l.unit.parts.clone();
+            for part in &mut parts {
+                let power = part.power as f64 * r.value;
+                if power.fract() != 0.0 {
+                    return Err(EvalError::UnitArgument(format!("^{}", r.value)));
+                }
+                part.power = power as i32;
+            }
+            parts.retain(|p| p.power != 0);
+            let value = check_overflow(l.value.powf(r.value), &[l.value, r.value])?;
+            Ok(Quantity { value, unit: Unit { parts } }.into_value())
+        }
+        BinOp::Add | BinOp::Sub | BinOp::Mod => {
+            let rv = r.convert(&l.unit).map_err(|_| incompatible(&l.unit, &r.unit))?;
+            let value = match op {
+                BinOp::Add => l.value + rv,
+                BinOp::Sub => l.value - rv,
+                _ => l.value % rv,
+            };
+            Ok(Quantity { value, unit: l.unit }.into_value())
+        }
+        op if op.is_comparison() => {
+            let rv = r.convert(&l.unit).map_err(|_| incompatible(&l.unit, &r.unit))?;
+            let ord = l.value.partial_cmp(&rv);
+            Ok(Value::Bool(match op {
+                BinOp::Eq => ord == Some(std::cmp::Ordering::Equal),
+                BinOp::Ne => ord != Some(std::cmp::Ordering::Equal),
+                BinOp::Lt => l.value < rv,
+                BinOp::Le => l.value <= rv,
+                BinOp::Gt => l.value > rv,
+                _ => l.value >= rv,
+            }))
+        }
+        op => Err(EvalError::UnitArgument(op.symbol().to_string())),
+    }
+}
+
+/* the known units; a name that is neither a variable nor a constant is
+   looked up here, so `5 km` is 5 times one kilometre */
+#[derive(Debug, Clone)]
+pub struct Units {
+    table: BTreeMap<String, UnitDef>,
+}
+
+impl Units {
+    pub fn new() -> Units {
+        Units { table: BTreeMap::new() }
+    }
+
+    /* adds or replaces a unit of `factor` SI base units of dimension `dim` */
+    pub fn define(&mut self, name: &str, factor: f64, dim: Dimension, description: &str) {
+        self.table.insert(name.to_string(), UnitDef {
+            name: name.to_string(),
+            factor,
+            dim,
+            description: description.to_string(),
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UnitDef> {
+        self.table.get(name)
+    }
+
+    /* one of the unit as a value */
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.get(name).map(|def| Value::Quantity(Quantity { value: 1.0, unit: Unit::named(def) }))
+    }
+
+    /* every unit, sorted by name */
+    pub fn iter(&self) -> impl Iterator<Item = &UnitDef> {
+        self.table.values()
+    }
+}
+
+impl Default for Units {
+    fn default() -> Units {
+        let mut units = Units::new();
+        for (name, factor, dim, description) in BUILTIN {
+            units.define(name, factor, Dimension(dim), description);
+        }
+        units
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::env::Environment;
+    use crate::parser::error::{Error, EvalError};
+
+    fn eval(input: &str) -> Result<String, Error> {
+        crate::eval(input, &mut Environment::new()).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn quantities_keep_their_units() {
+        assert_eq!(eval("5 km + 300 m").unwrap(), "5.3 km");
+        assert_eq!(eval("10 m / 2 s").unwrap(), "5 m/s");
+        assert_eq!(eval("3 ft * 2 ft").unwrap(), "6 ft^2");
+        assert_eq!(eval("1 kg in g").unwrap(), "1000 g");
+        match eval("1 m + 1 s") {
+            Err(Error::Eval(e)) => assert_eq!(e.node, EvalError::IncompatibleUnits("m".to_string(), "s".to_string())),
+            other => panic!("expected incompatible units, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn units_after_numbers_win_over_constants() {
+        assert_eq!(eval("5 h to min").unwrap(), "300 min");
+        assert_eq!(eval("100 km/h to m/s").unwrap(), eval("100000 m / 3600 s").unwrap());
+        assert_eq!(eval("90 min to h").unwrap(), "1.5 h");
+        assert_eq!(eval("g").unwrap(), "9.80665");
+        assert_eq!(eval("h / 6.62607015e-34").unwrap(), "1");
+        assert_eq!(eval("let m = 2; 3 m").unwrap(), "3 m");
+    }
+}
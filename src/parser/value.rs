@@ -0,0 +1,775 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Neg;
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Context, FromPrimitive, ToPrimitive, Zero};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{One, Signed};
+
+use crate::parser::ast::BinOp;
+use crate::parser::error::{check_overflow, EvalError};
+use crate::parser::settings::{ComplexStyle, FractionStyle, IntegerType, NumberMode, Settings};
+use crate::parser::units::{self, Quantity};
+
+/* largest integer exponent, and largest power of ten in a literal, that
+   rational mode expands exactly */
+pub const MAX_EXACT_EXPONENT: i64 = 100_000;
+
+/* largest power of ten a decimal-mode power may reach; the scale of a
+   BigDecimal is an i64, and squaring on the way doubles it */
+const MAX_DECIMAL_EXPONENT: f64 = 1e18;
+
+/* bitwise operators outside integer mode work on 128-bit integers, and a left
+   shift that does not fit is an overflow rather than wrapping */
+const UNBOUNDED: IntegerType = IntegerType { bits: 128, signed: true };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Float(f64),
+    Decimal(BigDecimal),
+    Rational(BigRational),
+    Complex(Complex64),
+    /* already wrapped to `Settings::integer` */
+    Integer(i128),
+    /* the result of a comparison or logical operator */
+    Bool(bool),
+    /* a number with a unit, such as `5 km` */
+    Quantity(Quantity),
+}
+
+impl Value {
+    /* reads a numeric literal in the representation of the current mode;
+       imaginary literals such as `2i` are complex in every mode */
+    pub fn from_literal(text: &str, settings: &Settings) -> Result<Value, EvalError> {
+        if let Some(im) = text.strip_suffix('i') {
+            return Ok(Value::Complex(Complex64::new(0.0, im.parse().unwrap_or(f64::NAN))));
+        }
+        Ok(match settings.mode {
+            NumberMode::Decimal => match BigDecimal::from_str(text) {
+                Ok(d) => Value::Decimal(round(d, settings.precision)),
+                Err(_) => Value::Float(text.parse().unwrap_or(f64::NAN)),
+            },
+            NumberMode::Rational => match BigDecimal::from_str(text) {
+                Ok(d) => Value::Rational(decimal_to_rational(&d)?),
+                Err(_) => Value::Float(text.parse().unwrap_or(f64::NAN)),
+            },
+            NumberMode::Integer => match BigDecimal::from_str(text) {
+                Ok(d) => decimal_to_integer(&d, settings)?,
+                Err(_) => Value::Float(f64::NAN),
+            },
+            NumberMode::Float | NumberMode::Complex => Value::Float(text.parse().unwrap_or(f64::NAN)),
+        })
+    }
+
+    /* an exact integer in the representation of the current mode */
+    pub fn from_integer(x: i128, settings: &Settings) -> Value {
+        match settings.mode {
+            NumberMode::Integer => Value::Integer(settings.integer.wrap(x)),
+            NumberMode::Decimal => Value::Decimal(round(BigDecimal::from(x), settings.precision)),
+            NumberMode::Rational => Value::Rational(BigRational::from_integer(BigInt::from(x))),
+            NumberMode::Float | NumberMode::Complex => Value::Float(x as f64),
+        }
+    }
+
+    /* wraps a float result, converting it to the current mode's representation;
+       rational mode keeps it as a float since the result is already inexact,
+       decimal mode keeps only the digits an f64 actually carries, and integer
+       mode refuses a fraction rather than truncating it */
+    pub fn from_f64(x: f64, settings: &Settings) -> Result<Value, EvalError> {
+        Ok(match settings.mode {
+            NumberMode::Integer => match BigInt::from_f64(x) {
+                _ if x.is_infinite() => return Err(EvalError::Overflow),
+                Some(n) if x.fract() == 0.0 => Value::Integer(wrap_bigint(&n, settings.integer)),
+                _ => return Err(EvalError::NotAnInteger(x.to_string())),
+            },
+            NumberMode::Decimal => match to_decimal(x) {
+                Some(d) => Value::Decimal(round(d, settings.precision.min(f64::DIGITS as u64))),
+                None => Value::Float(x),
+            },
+            NumberMode::Float | NumberMode::Rational | NumberMode::Complex => Value::Float(x),
+        })
+    }
+
+    /* the real value; complex numbers with an imaginary part have none and give NaN */
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Float(x) => *x,
+            Value::Decimal(d) => d.to_f64().unwrap_or(f64::NAN),
+            Value::Rational(q) => q.to_f64().unwrap_or(f64::NAN),
+            Value::Complex(c) if c.im == 0.0 => c.re,
+            Value::Complex(_) => f64::NAN,
+            Value::Integer(n) => *n as f64,
+            Value::Bool(_) | Value::Quantity(_) => f64::NAN,
+        }
+    }
+
+    /* the exact integer value, for the bitwise operators named by `op` */
+    pub fn to_integral(&self, op: &str) -> Result<i128, EvalError> {
+        let domain_error = || EvalError::DomainError { function: op.to_string(), arg: self.to_f64() };
+        match self {
+            Value::Integer(n) => Ok(*n),
+            Value::Complex(_) => Err(EvalError::ComplexArgument(op.to_string())),
+            Value::Bool(_) => Err(EvalError::BooleanArgument(op.to_string())),
+            Value::Quantity(_) => Err(EvalError::UnitArgument(op.to_string())),
+            Value::Decimal(d) if d.is_integer() => d.to_i128().ok_or_else(domain_error),
+            Value::Rational(q) if q.is_integer() => q.to_integer().to_i128().ok_or_else(domain_error),
+            Value::Float(x) if x.fract() == 0.0 && x.abs() < 2f64.powi(127) => Ok(*x as i128),
+            _ => Err(domain_error()),
+        }
+    }
+
+    /* brings an integer back into range after an operation that may leave it */
+    pub fn wrapped(self, settings: &Settings) -> Value {
+        match self {
+            Value::Integer(n) => Value::Integer(settings.integer.wrap(n)),
+            v => v,
+        }
+    }
+
+    pub fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Complex(c) => *c,
+            v => Complex64::new(v.to_f64(), 0.0),
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(self, Value::Complex(_))
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Value::Bool(_))
+    }
+
+    pub fn is_quantity(&self) -> bool {
+        matches!(self, Value::Quantity(_))
+    }
+
+    /* how a condition reads the value: any non-zero number counts as true */
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            v => !v.is_zero(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Value::Float(x) => *x == 0.0,
+            Value::Decimal(d) => d.is_zero(),
+            Value::Rational(q) => q.is_zero(),
+            Value::Complex(c) => c.is_zero(),
+            Value::Integer(n) => *n == 0,
+            Value::Bool(_) => false,
+            Value::Quantity(q) => q.value == 0.0,
+        }
+    }
+
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Decimal(l), Value::Decimal(r)) => Some(l.cmp(r)),
+            (Value::Rational(l), Value::Rational(r)) => Some(l.cmp(r)),
+            (Value::Integer(l), Value::Integer(r)) => Some(l.cmp(r)),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+
+    pub fn binary(op: BinOp, l: Value, r: Value, settings: &Settings) -> Result<Value, EvalError> {
+        if l.is_bool() || r.is_bool() {
+            return match (op, l, r) {
+                (BinOp::Eq, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l == r)),
+                (BinOp::Ne, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l != r)),
+                _ => Err(EvalError::BooleanArgument(op.symbol().to_string())),
+            };
+        }
+        if l.is_quantity() || r.is_quantity() {
+            return units::quantity_binary(op, l, r);
+        }
+        if op.is_comparison() {
+            return compare_binary(op, &l, &r).map(Value::Bool);
+        }
+        if op.is_bitwise() {
+            let (l, r) = (l.to_integral(op.symbol())?, r.to_integral(op.symbol())?);
+            let res = if settings.mode == NumberMode::Integer {
+                integer_binary(op, l, r, settings.integer)?
+            } else if op == BinOp::Shl && r >= 0 {
+                exact_shl(l, r).ok_or(EvalError::Overflow)?
+            } else {
+                integer_binary(op, l, r, UNBOUNDED)?
+            };
+            return Ok(Value::from_integer(res, settings));
+        }
+        if l.is_complex() || r.is_complex() || needs_complex(op, &l, &r, settings) {
+            return complex_binary(op, l.to_complex(), r.to_complex()).map(Value::Complex);
+        }
+        match promote(l, r, settings)? {
+            (Value::Decimal(l), Value::Decimal(r)) => decimal_binary(op, l, r, settings),
+            (Value::Rational(l), Value::Rational(r)) => rational_binary(op, l, r, settings),
+            (Value::Integer(l), Value::Integer(r)) => {
+                integer_binary(op, l, r, settings.integer).map(Value::Integer)
+            }
+            (l, r) => float_binary(op, l.to_f64(), r.to_f64()).map(Value::Float),
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Float(x) => Value::Float(-x),
+            Value::Decimal(d) => Value::Decimal(-d),
+            Value::Rational(q) => Value::Rational(-q),
+            Value::Complex(c) => Value::Complex(-c),
+            // may leave the range of a signed type; see `wrapped`
+            Value::Integer(n) => Value::Integer(n.wrapping_neg()),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Quantity(q) => Value::Quantity(Quantity { value: -q.value, ..q }),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::Float(x)
+    }
+}
+
+impl Value {
+    /* renders the value for display, honouring the fraction and complex styles */
+    pub fn format(&self, settings: &Settings) -> String {
+        match self {
+            Value::Complex(c) if settings.complex == ComplexStyle::Polar => {
+                let (r, theta) = c.to_polar();
+                format!("{} ∠ {}", r, settings.angle.from_radians(tidy(theta, r)))
+            }
+            Value::Rational(q) => match settings.fraction {
+                FractionStyle::Fraction => self.to_string(),
+                FractionStyle::Mixed => {
+                    let whole = q.trunc();
+                    let part = (q - &whole).abs();
+                    if whole.is_zero() || part.is_zero() {
+                        self.to_string()
+                    } else {
+                        format!("{} {}", whole, part)
+                    }
+                }
+                FractionStyle::Decimal => {
+                    let ctx = Context::default().with_prec(settings.precision).unwrap_or_default();
+                    let d = ctx.multiply(
+                        &BigDecimal::from(q.numer().clone()),
+                        &ctx.invert(&BigDecimal::from(q.denom().clone())));
+                    Value::Decimal(d).to_string()
+                }
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // -0 is an artifact of negation and only confuses
+            Value::Float(x) if *x == 0.0 => write!(f, "0"),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Rational(q) => write!(f, "{}", q),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::Complex(c) => {
+                let norm = c.norm();
+                let (re, im) = (tidy(c.re, norm), tidy(c.im, norm));
+                let imag = if im.abs() == 1.0 { "i".to_string() } else { format!("{}i", im.abs()) };
+                if im == 0.0 {
+                    write!(f, "{}", re)
+                } else if re == 0.0 {
+                    write!(f, "{}{}", if im < 0.0 { "-" } else { "" }, imag)
+                } else {
+                    write!(f, "{} {} {}", re, if im < 0.0 { '-' } else { '+' }, imag)
+                }
+            }
+            Value::Decimal(d) => {
+                let d = d.normalized();
+                let magnitude = if d.is_zero() { 0 } else { d.order_of_magnitude().abs() };
+                if magnitude as u64 > d.digits() + 20 {
+                    write!(f, "{}", d.to_scientific_notation())
+                } else {
+                    write!(f, "{}", d.to_plain_string())
+                }
+            }
+        }
+    }
+}
+
+/* brings both operands to the representation of the current mode */
+fn promote(l: Value, r: Value, settings: &Settings) -> Result<(Value, Value), EvalError> {
+    Ok(match settings.mode {
+        NumberMode::Decimal => (as_decimal(l, settings)?, as_decimal(r, settings)?),
+        NumberMode::Rational => match (as_rational(l), as_rational(r)) {
+            (l @ Value::Rational(_), r @ Value::Rational(_)) => (l, r),
+            (l, r) => (Value::Float(l.to_f64()), Value::Float(r.to_f64())),
+        },
+        NumberMode::Integer => (as_integer(l, settings)?, as_integer(r, settings)?),
+        NumberMode::Float | NumberMode::Complex => (Value::Float(l.to_f64()), Value::Float(r.to_f64())),
+    })
+}
+
+fn as_integer(v: Value, settings: &Settings) -> Result<Value, EvalError> {
+    match v {
+        Value::Integer(n) => Ok(Value::Integer(n)),
+        Value::Decimal(d) => decimal_to_integer(&d, settings),
+        Value::Rational(q) if q.is_integer() => Ok(Value::Integer(wrap_bigint(&q.to_integer(), settings.integer))),
+        Value::Rational(q) => Err(EvalError::NotAnInteger(q.to_string())),
+        v => Value::from_f64(v.to_f64(), settings),
+    }
+}
+
+/* a whole decimal wrapped to the word size; 10^k is a multiple of 2^128 from
+   k = 128 on, so larger powers of ten wrap to nothing and need not be expanded */
+fn decimal_to_integer(d: &BigDecimal, settings: &Settings) -> Result<Value, EvalError> {
+    if !d.is_integer() {
+        return Err(EvalError::NotAnInteger(d.to_string()));
+    }
+    let (digits, scale) = d.as_bigint_and_exponent();
+    let n = match scale {
+        s if s <= -128 => BigInt::zero(),
+        s if s < 0 => digits * num_traits::pow(BigInt::from(10), (-s) as usize),
+        _ => d.with_scale(0).as_bigint_and_exponent().0,
+    };
+    Ok(Value::Integer(wrap_bigint(&n, settings.integer)))
+}
+
+/* reduces an arbitrarily large integer modulo 2^bits into the range of `ty` */
+fn wrap_bigint(n: &BigInt, ty: IntegerType) -> i128 {
+    let low = n & BigInt::from(u128::MAX);
+    ty.wrap(low.to_u128().unwrap_or(0) as i128)
+}
+
+/* rounding noise such as the 1.2e-16i in exp(i*pi) is shown as zero */
+fn tidy(x: f64, norm: f64) -> f64 {
+    if x.abs() < norm * 1e-15 { 0.0 } else { x }
+}
+
+/* complex mode answers powers of negative numbers with complex results */
+fn needs_complex(op: BinOp, l: &Value, r: &Value, settings: &Settings) -> bool {
+    settings.mode == NumberMode::Complex
+        && op == BinOp::Pow
+        && l.to_f64() < 0.0
+        && r.to_f64().fract() != 0.0
+}
+
+fn as_decimal(v: Value, settings: &Settings) -> Result<Value, EvalError> {
+    match v {
+        Value::Float(x) => Value::from_f64(x, settings),
+        d => Ok(d),
+    }
+}
+
+/* a decimal too large or too small to hold as a fraction stays as it is and
+   the arithmetic falls back to floating point */
+fn as_rational(v: Value) -> Value {
+    match v {
+        Value::Decimal(d) => match decimal_to_rational(&d) {
+            Ok(q) => Value::Rational(q),
+            Err(_) => Value::Decimal(d),
+        },
+        v => v,
+    }
+}
+
+/* the exact fraction for `d`; the power of ten is limited like an exponent,
+   since 1e999999999 would otherwise take forever to expand */
+pub fn decimal_to_rational(d: &BigDecimal) -> Result<BigRational, EvalError> {
+    let (digits, scale) = d.as_bigint_and_exponent();
+    if scale.abs() > MAX_EXACT_EXPONENT {
+        return Err(EvalError::ExponentLimit(MAX_EXACT_EXPONENT));
+    }
+    let ten = BigInt::from(10);
+    Ok(if scale >= 0 {
+        BigRational::new(digits, num_traits::pow(ten, scale as usize))
+    } else {
+        BigRational::from_integer(digits * num_traits::pow(ten, (-scale) as usize))
+    })
+}
+
+/* the shortest decimal that reads back as `x`, so 0.1 becomes exactly 0.1 */
+fn to_decimal(x: f64) -> Option<BigDecimal> {
+    if !x.is_finite() {
+        return None;
+    }
+    BigDecimal::from_str(&x.to_string()).ok().or_else(|| BigDecimal::from_f64(x))
+}
+
+pub fn round(d: BigDecimal, precision: u64) -> BigDecimal {
+    if d.digits() > precision {
+        d.with_prec(precision)
+    } else {
+        d
+    }
+}
+
+fn float_binary(op: BinOp, l: f64, r: f64) -> Result<f64, EvalError> {
+    let res = match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div => l / r,
+        BinOp::Mod => l % r,
+        BinOp::Pow => {
+            if l == 0.0 && r < 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            let res = l.powf(r);
+            if res.is_nan() && !l.is_nan() && !r.is_nan() {
+                return Err(EvalError::DomainError { function: "pow".to_string(), arg: l });
+            }
+            res
+        }
+        op => unreachable!("{} is handled by Value::binary", op.symbol()),
+    };
+    check_overflow(res, &[l, r])
+}
+
+fn decimal_binary(op: BinOp, l: BigDecimal, r: BigDecimal, settings: &Settings)
+        -> Result<Value, EvalError> {
+    let precision = settings.precision;
+    // a few guard digits so the final rounding is correct
+    let ctx = Context::default().with_prec(precision + 5).unwrap_or_default();
+    let res = match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div => ctx.multiply(&l, &ctx.invert(&r)),
+        BinOp::Mod => l % r,
+        BinOp::Pow => {
+            if l.is_zero() && r < BigDecimal::zero() {
+                return Err(EvalError::DivisionByZero);
+            }
+            // integer powers square and multiply, rounding to the working
+            // precision at each step, so even large exponents are quick
+            let twice = r.double();
+            match (r.to_i64(), twice.to_i64()) {
+                (Some(e), _) if r.is_integer() => decimal_powi(&l, e, &ctx)?,
+                // half-integer powers are a square root away from an integer power
+                (_, Some(e)) if twice.is_integer() => {
+                    return decimal_sqrt(&decimal_powi(&l, e, &ctx)?, settings).map_err(|_| {
+                        EvalError::DomainError { function: "pow".to_string(), arg: l.to_f64().unwrap_or(f64::NAN) }
+                    });
+                }
+                _ => {
+                    let x = float_binary(op, l.to_f64().unwrap_or(f64::NAN), r.to_f64().unwrap_or(f64::NAN))?;
+                    return Value::from_f64(x, settings);
+                }
+            }
+        }
+        op => unreachable!("{} is handled by Value::binary", op.symbol()),
+    };
+    Ok(Value::Decimal(round(res, precision)))
+}
+
+/* `l^e` rounded to the precision of `ctx`; a power of ten beyond what a
+   BigDecimal can hold is an overflow, or zero when the result shrinks */
+fn decimal_powi(l: &BigDecimal, e: i64, ctx: &Context) -> Result<BigDecimal, EvalError> {
+    let magnitude = l.order_of_magnitude();
+    // |log10 |l|| is at most |magnitude| + 1, so this bounds the result's power of ten
+    let bound = e.unsigned_abs() as f64 * (magnitude.unsigned_abs() as f64 + 1.0);
+    if bound > MAX_DECIMAL_EXPONENT && !(l.is_zero() || l.abs().is_one()) {
+        return if (magnitude >= 0) == (e > 0) { Err(EvalError::Overflow) } else { Ok(BigDecimal::zero()) };
+    }
+    Ok(l.powi_with_context(e, ctx))
+}
+
+/* the square root to the full working precision rather than through f64 */
+pub fn decimal_sqrt(d: &BigDecimal, settings: &Settings) -> Result<Value, EvalError> {
+    let ctx = Context::default().with_prec(settings.precision + 5).unwrap_or_default();
+    match d.sqrt_with_context(&ctx) {
+        Some(root) => Ok(Value::Decimal(round(root, settings.precision))),
+        None => Err(EvalError::DomainError { function: "sqrt".to_string(), arg: d.to_f64().unwrap_or(f64::NAN) }),
+    }
+}
+
+fn rational_binary(op: BinOp, l: BigRational, r: BigRational, settings: &Settings)
+        -> Result<Value, EvalError> {
+    let res = match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div => l / r,
+        BinOp::Mod => l % r,
+        BinOp::Pow => {
+            if l.is_zero() && r.is_negative() {
+                return Err(EvalError::DivisionByZero);
+            }
+            match r.to_integer().to_i32() {
+                Some(e) if r.is_integer() && (e as i64).abs() <= MAX_EXACT_EXPONENT => {
+                    if e == 0 { BigRational::one() } else { l.pow(e) }
+                }
+                // these powers are exact whatever the exponent
+                _ if r.is_integer() && (l.is_zero() || l.abs().is_one()) => {
+                    let odd = !(r.to_integer() % BigInt::from(2)).is_zero();
+                    if odd { l } else { l.abs() }
+                }
+                // beyond the limit the result would silently underflow or overflow a float
+                _ if r.is_integer() => return Err(EvalError::ExponentLimit(MAX_EXACT_EXPONENT)),
+                _ => {
+                    let x = float_binary(op, l.to_f64().unwrap_or(f64::NAN), r.to_f64().unwrap_or(f64::NAN))?;
+                    return Value::from_f64(x, settings);
+                }
+            }
+        }
+        op => unreachable!("{} is handled by Value::binary", op.symbol()),
+    };
+    Ok(Value::Rational(res))
+}
+
+fn complex_binary(op: BinOp, l: Complex64, r: Complex64) -> Result<Complex64, EvalError> {
+    let res = match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div => l / r,
+        BinOp::Mod => return Err(EvalError::ComplexArgument("%".to_string())),
+        BinOp::Pow => {
+            if l.is_zero() {
+                return match r.re {
+                    e if e > 0.0 => Ok(Complex64::zero()),
+                    e if e == 0.0 && r.im == 0.0 => Ok(Complex64::one()),
+                    _ => Err(EvalError::DivisionByZero),
+                };
+            }
+            // integer powers by repeated multiplication keep i^2 exactly -1
+            match r.re.to_i32() {
+                Some(e) if r.im == 0.0 && r.re.fract() == 0.0 => l.powi(e),
+                _ => l.powc(r),
+            }
+        }
+        op => unreachable!("{} is handled by Value::binary", op.symbol()),
+    };
+    check_overflow(res.re, &[l.re, l.im, r.re, r.im])?;
+    check_overflow(res.im, &[l.re, l.im, r.re, r.im])?;
+    Ok(res)
+}
+
+/* complex numbers have no order, only equality; NaN compares false against everything */
+fn compare_binary(op: BinOp, l: &Value, r: &Value) -> Result<bool, EvalError> {
+    if l.is_complex() || r.is_complex() {
+        return match op {
+            BinOp::Eq => Ok(l.to_complex() == r.to_complex()),
+            BinOp::Ne => Ok(l.to_complex() != r.to_complex()),
+            _ => Err(EvalError::ComplexArgument(op.symbol().to_string())),
+        };
+    }
+    let ord = l.compare(r);
+    Ok(match op {
+        BinOp::Eq => ord == Some(Ordering::Equal),
+        BinOp::Ne => ord != Some(Ordering::Equal),
+        BinOp::Lt => ord == Some(Ordering::Less),
+        BinOp::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+        BinOp::Gt => ord == Some(Ordering::Greater),
+        BinOp::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+        op => unreachable!("{} is not a comparison", op.symbol()),
+    })
+}
+
+/* two's complement arithmetic wrapped to `ty` */
+fn integer_binary(op: BinOp, l: i128, r: i128, ty: IntegerType) -> Result<i128, EvalError> {
+    let res = match op {
+        BinOp::Add => l.wrapping_add(r),
+        BinOp::Sub => l.wrapping_sub(r),
+        BinOp::Mul => l.wrapping_mul(r),
+        BinOp::Div | BinOp::Mod if r == 0 => return Err(EvalError::DivisionByZero),
+        BinOp::Div => l.wrapping_div(r),
+        BinOp::Mod => l.wrapping_rem(r),
+        BinOp::Pow => {
+            if r < 0 {
+                return Err(EvalError::DomainError { function: "pow".to_string(), arg: r as f64 });
+            }
+            wrapping_pow(l, r as u128)
+        }
+        BinOp::BitAnd => l & r,
+        BinOp::BitOr => l | r,
+        BinOp::BitXor => l ^ r,
+        BinOp::Shl | BinOp::Shr if r < 0 => {
+            return Err(EvalError::DomainError { function: op.symbol().to_string(), arg: r as f64 });
+        }
+        BinOp::Shl if r >= 128 => 0,
+        BinOp::Shl => l << r,
+        // unsigned values are never negative, so this is a logical shift for them
+        BinOp::Shr => l >> r.min(127),
+        op => unreachable!("{} is handled by Value::binary", op.symbol()),
+    };
+    Ok(ty.wrap(res))
+}
+
+/* `l * 2^r`, or None when it does not fit in an i128 */
+fn exact_shl(l: i128, r: i128) -> Option<i128> {
+    if l == 0 {
+        return Some(0);
+    }
+    let res = l.checked_shl(u32::try_from(r).ok()?)?;
+    (res >> r == l).then_some(res)
+}
+
+/* square and multiply; wrapping modulo 2^128 keeps the low bits every type needs */
+fn wrapping_pow(mut base: i128, mut exp: u128) -> i128 {
+    let mut acc: i128 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = acc.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_EXACT_EXPONENT;
+    use crate::parser::env::Environment;
+    use crate::parser::error::{Error, EvalError};
+    use crate::parser::settings::{ComplexStyle, NumberMode};
+
+    /* evaluates in a fresh environment in `mode` and formats the result */
+    fn eval_in(mode: NumberMode, input: &str) -> Result<String, EvalError> {
+        let mut env = Environment::new();
+        env.settings.mode = mode;
+        env.settings.precision = 30;
+        match crate::eval(input, &mut env) {
+            Ok(value) => Ok(value.format(&env.settings)),
+            Err(Error::Eval(e)) => Err(e.node),
+            Err(e) => panic!("{} does not parse: {:?}", input, e),
+        }
+    }
+
+    fn limit() -> EvalError {
+        EvalError::ExponentLimit(MAX_EXACT_EXPONENT)
+    }
+
+    fn decimal(input: &str) -> String {
+        eval_in(NumberMode::Decimal, input).unwrap()
+    }
+
+    #[test]
+    fn each_mode_has_its_own_arithmetic() {
+        let cases = [
+            ("0.1 + 0.2", "0.30000000000000004", "0.3", "3/10"),
+            ("1/3", "0.3333333333333333", "0.333333333333333333333333333333", "1/3"),
+            ("2^-2", "0.25", "0.25", "1/4"),
+            ("-7 % 3", "-1", "-1", "-1"),
+        ];
+        for (input, float, decimal, rational) in cases {
+            assert_eq!(eval_in(NumberMode::Float, input).unwrap(), float, "{}", input);
+            assert_eq!(eval_in(NumberMode::Decimal, input).unwrap(), decimal, "{}", input);
+            assert_eq!(eval_in(NumberMode::Rational, input).unwrap(), rational, "{}", input);
+        }
+        for mode in NumberMode::ALL {
+            assert_eq!(eval_in(mode, "1/0"), Err(EvalError::DivisionByZero), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn complex_arithmetic() {
+        let complex = |input| eval_in(NumberMode::Complex, input).unwrap();
+        assert_eq!(complex("i^2"), "-1");
+        assert_eq!(complex("(1+2i)*(3-i)"), "5 + 5i");
+        assert_eq!(complex("(1+i)/(1-i)"), "i");
+        assert_eq!(complex("conj(3+4i)"), "3 - 4i");
+        assert_eq!(complex("abs(3+4i) + re(3+4i) + im(3+4i)"), "12");
+        assert_eq!(complex("exp(i*pi)"), "-1");
+        assert_eq!(eval_in(NumberMode::Complex, "(1+i) % 2"), Err(EvalError::ComplexArgument("%".to_string())));
+    }
+
+    #[test]
+    fn only_complex_mode_widens_real_arguments() {
+        assert_eq!(eval_in(NumberMode::Complex, "sqrt(-4)").unwrap(), "2i");
+        assert_eq!(eval_in(NumberMode::Complex, "ln(-1)").unwrap(), "3.141592653589793i");
+        assert!(matches!(eval_in(NumberMode::Float, "sqrt(-4)"), Err(EvalError::DomainError { .. })));
+        // an explicit imaginary part is complex in every mode
+        assert_eq!(eval_in(NumberMode::Float, "sqrt(-4 + 0i)").unwrap(), "2i");
+    }
+
+    #[test]
+    fn polar_display() {
+        let mut env = Environment::new();
+        env.settings.mode = NumberMode::Complex;
+        env.settings.complex = ComplexStyle::Polar;
+        let value = crate::eval("1 + i", &mut env).unwrap();
+        assert_eq!(value.format(&env.settings), "1.4142135623730951 ∠ 0.7853981633974483");
+    }
+
+    #[test]
+    fn integer_mode_wraps_but_never_truncates() {
+        let integer = |input| eval_in(NumberMode::Integer, input);
+        assert_eq!(integer("9223372036854775807 + 1").unwrap(), "-9223372036854775808");
+        assert_eq!(integer("sqrt(16) + 2.0").unwrap(), "6");
+        assert_eq!(integer("1e999999999").unwrap(), "0");
+        assert_eq!(integer("2^0.5"), Err(EvalError::NotAnInteger("0.5".to_string())));
+        assert_eq!(integer("2.5!"), Err(EvalError::NotAnInteger("2.5".to_string())));
+        assert_eq!(integer("sqrt(2)"), Err(EvalError::NotAnInteger("1.4142135623730951".to_string())));
+        assert!(matches!(integer("2 * pi"), Err(EvalError::NotAnInteger(_))));
+        assert_eq!(integer("fact(171)"), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn integer_division_truncates_toward_zero() {
+        let integer = |input| eval_in(NumberMode::Integer, input);
+        assert_eq!(integer("7 / 2").unwrap(), "3");
+        assert_eq!(integer("-7 / 2").unwrap(), "-3");
+    }
+
+    #[test]
+    fn shifts_only_wrap_in_integer_mode() {
+        for mode in [NumberMode::Float, NumberMode::Decimal, NumberMode::Rational] {
+            assert!(eval_in(mode, "1 << 126").is_ok(), "{}", mode);
+            for input in ["1 << 127", "3 << 126", "1 << 128", "-3 << 126"] {
+                assert_eq!(eval_in(mode, input), Err(EvalError::Overflow), "{} in {}", input, mode);
+            }
+            assert_eq!(eval_in(mode, "0 << 1000").unwrap(), "0", "{}", mode);
+        }
+        assert_eq!(eval_in(NumberMode::Rational, "-1 << 127").unwrap(), "-170141183460469231731687303715884105728");
+        assert_eq!(eval_in(NumberMode::Integer, "1 << 64").unwrap(), "0");
+        assert_eq!(eval_in(NumberMode::Integer, "3 << 62").unwrap(), "-4611686018427387904");
+    }
+
+    #[test]
+    fn rational_mode_limits_exponents() {
+        let rational = |input| eval_in(NumberMode::Rational, input);
+        assert_eq!(rational("(1/3)^1000000"), Err(limit()));
+        assert_eq!(rational("(-1)^1000001").unwrap(), "-1");
+        assert_eq!(rational("1^1000000").unwrap(), "1");
+        assert_eq!(rational("1e999999999"), Err(limit()));
+        assert_eq!(rational("1.5e-5").unwrap(), "3/200000");
+    }
+
+    #[test]
+    fn decimal_powers_round_at_each_step() {
+        assert_eq!(decimal("1.0001^1000000"), "26747109931421401729483544817900000000000000");
+        assert_eq!(decimal("2^200000"), "9.98005181847120956085934630921e60205");
+        assert_eq!(decimal("(1/3)^1000000"), "5.56263209915712886588210929999e-477122");
+        assert_eq!(decimal("(-1)^1000001"), "-1");
+        assert_eq!(eval_in(NumberMode::Decimal, "2^9223372036854775807"), Err(EvalError::Overflow));
+        assert_eq!(decimal("2^-9223372036854775807"), "0");
+    }
+
+    #[test]
+    fn decimal_square_roots_keep_full_precision() {
+        assert_eq!(decimal("sqrt(2)"), "1.41421356237309504880168872421");
+        assert_eq!(decimal("2^0.5"), "1.41421356237309504880168872421");
+        assert_eq!(decimal("2^1.5"), "2.82842712474619009760337744842");
+        assert_eq!(decimal("4^-0.5"), "0.5");
+        assert!(eval_in(NumberMode::Decimal, "sqrt(-1)").is_err());
+        assert!(eval_in(NumberMode::Decimal, "(-4)^0.5").is_err());
+    }
+
+    #[test]
+    fn decimal_results_computed_in_floating_point_show_only_its_digits() {
+        assert_eq!(decimal("2^0.3"), "1.23114441334492");
+        assert_eq!(decimal("sin(1)"), "0.841470984807897");
+    }
+}